@@ -0,0 +1,115 @@
+//! Lexes and parses generated programs of growing size.
+//!
+//! Run with `cargo bench`. Allocations per token should stay well below
+//! one: identifiers and plain string literals are interned, so repeated
+//! text costs nothing once seen, and the parser works on slices of the
+//! token stream.
+//!
+//! Time grows linearly with the input, but parsing the smallest program
+//! runs faster than the rest. With glibc, a freed block of up to 32 MiB is
+//! reused by the next run, while the larger token buffers are mapped
+//! afresh every time and page faulted in again. Setting
+//! `MALLOC_MMAP_THRESHOLD_` above their size keeps parse throughput flat.
+
+use jetlang::driver;
+use jetlang::lexer::Lexer;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const RUNS: usize = 5;
+
+/// A program of at least `size` bytes built from numbered copies of one chunk.
+fn program(size: usize) -> String {
+    let mut source = String::with_capacity(size + 512);
+    let mut index = 0;
+    while source.len() < size {
+        source.push_str(&format!(
+            concat!(
+                "/// Adds two numbers.\n",
+                "fn add_{0}(int left, int right): int {{\n",
+                "    let total: int = 1 + 2 * 3\n",
+                "    if 1 < 2 {{\n",
+                "        print(\"left is {{left}} and right is {{right}}\\n\")\n",
+                "    }}\n",
+                "    return 40 + right\n",
+                "}}\n",
+                "let result_{0}: int = add_{0}(1, 0x2a)\n",
+                "print(\"a plain string literal without escapes\")\n",
+            ),
+            index,
+        ));
+        index += 1;
+    }
+    source
+}
+
+/// Runs `stage` a few times and returns the fastest run together with the
+/// allocations of the last one. Every run interns its strings afresh.
+fn measure<T>(mut stage: impl FnMut() -> T) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut allocations = 0;
+    for _ in 0..RUNS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        let result = stage();
+        best = best.min(start.elapsed());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        drop(result);
+    }
+    (best, allocations)
+}
+
+fn throughput(bytes: usize, time: Duration) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / time.as_secs_f64()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>10} {:>10} {:>9} {:>12} {:>10} {:>9} {:>12}",
+        "size", "tokens", "lex", "MiB/s", "allocs/tok", "parse", "MiB/s", "allocs/tok",
+    );
+    for mebibytes in [1, 2, 4, 8] {
+        let source = program(mebibytes * 1024 * 1024);
+        let tokens = Lexer::new(&source).count();
+
+        let (lex, lex_allocations) = measure(|| Lexer::new(&source).filter(Result::is_ok).count());
+        let (parse, parse_allocations) = measure(|| driver::parse(&source).expect("generated program parses"));
+        println!(
+            "{:>5}MiB {:>10} {:>8.1}ms {:>9.1} {:>12.3} {:>8.1}ms {:>9.1} {:>12.3}",
+            mebibytes,
+            tokens,
+            lex.as_secs_f64() * 1000.0,
+            throughput(source.len(), lex),
+            lex_allocations as f64 / tokens as f64,
+            parse.as_secs_f64() * 1000.0,
+            throughput(source.len(), parse),
+            parse_allocations as f64 / tokens as f64,
+        );
+    }
+}
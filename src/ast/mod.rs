@@ -1,349 +1,900 @@
 use crate::lexer;
+use crate::diagnostic::Diagnostic;
+use crate::symbol::Symbol;
 use lexer::Token;
-use std::process;
 
 
 #[derive(Debug)]
 pub enum Node {
-    Ident(String, lexer::SourceLocation),
-    Str(String, lexer::SourceLocation),
+    Ident(Symbol, lexer::SourceLocation),
+    Str(Symbol, lexer::SourceLocation),
+    /// A string literal with embedded expressions; text parts are `Node::Str`.
+    Interpolation(Vec<Node>, lexer::SourceLocation),
     Int(usize, lexer::SourceLocation),
+    Float(f64, lexer::SourceLocation),
+    Char(char, lexer::SourceLocation),
 
     Nop,
 
     Comparison {
-        rexpr: (Box<Node>, lexer::SourceLocation),
         lexpr: (Box<Node>, lexer::SourceLocation),
+        rexpr: (Box<Node>, lexer::SourceLocation),
         op: (lexer::Comparison, lexer::SourceLocation),
     },
+    /// `lexpr` is the left operand and `rexpr` the right one.
     BinaryExpr {
-        rexpr: (Box<Node>, lexer::SourceLocation),
         lexpr: (Box<Node>, lexer::SourceLocation),
+        rexpr: (Box<Node>, lexer::SourceLocation),
         op: (lexer::Operator, lexer::SourceLocation),
     },
+    Unary {
+        op: (lexer::Operator, lexer::SourceLocation),
+        expr: (Box<Node>, lexer::SourceLocation),
+    },
     Function {
-        name: (String, lexer::SourceLocation),
-        parameters: (Vec<(String, lexer::Type, lexer::SourceLocation)>, lexer::SourceLocation),
+        name: (Symbol, lexer::SourceLocation),
+        parameters: (Vec<(Symbol, lexer::Type, lexer::SourceLocation)>, lexer::SourceLocation),
         return_type: (lexer::Type, lexer::SourceLocation),
-        body: (Vec<Node>, lexer::SourceLocation)
+        body: (Vec<Node>, lexer::SourceLocation),
+        docs: Vec<Symbol>,
     },
     Return {
         expr: (Box<Node>, lexer::SourceLocation),
     },
     Call {
-        name: (String, lexer::SourceLocation),
+        name: (Symbol, lexer::SourceLocation),
         parameters: (Vec<Node>, lexer::SourceLocation),
     },
     Variable {
-        name: (String, lexer::SourceLocation),
+        name: (Symbol, lexer::SourceLocation),
         var_type: (lexer::Type, lexer::SourceLocation),
         expr: (Box<Node>, lexer::SourceLocation),
+        docs: Vec<Symbol>,
     },
     If {
         test: (Box<Node>, lexer::SourceLocation),
         then_body: (Vec<Node>, lexer::SourceLocation),
         else_body: (Vec<Node>, lexer::SourceLocation),
+    },
+    While {
+        label: Option<(Symbol, lexer::SourceLocation)>,
+        test: (Box<Node>, lexer::SourceLocation),
+        body: (Vec<Node>, lexer::SourceLocation),
+    },
+    /// `for variable in start..end`, or `start..=end` when `inclusive`.
+    For {
+        label: Option<(Symbol, lexer::SourceLocation)>,
+        variable: (Symbol, lexer::SourceLocation),
+        start: (Box<Node>, lexer::SourceLocation),
+        end: (Box<Node>, lexer::SourceLocation),
+        inclusive: bool,
+        body: (Vec<Node>, lexer::SourceLocation),
+    },
+    /// `break` or `break 'label`; `location` is that of the keyword.
+    Break {
+        label: Option<(Symbol, lexer::SourceLocation)>,
+        location: lexer::SourceLocation,
+    },
+    Continue {
+        label: Option<(Symbol, lexer::SourceLocation)>,
+        location: lexer::SourceLocation,
+    },
+    /// A nested scope; only produced by lowering.
+    Block {
+        body: (Vec<Node>, lexer::SourceLocation),
+    },
+    /// `target = expr`, where `target` is a variable or a dereference.
+    Assign {
+        target: (Box<Node>, lexer::SourceLocation),
+        expr: (Box<Node>, lexer::SourceLocation),
+    },
+}
+
+impl Node {
+    /// Location used when reporting diagnostics about this node.
+    pub fn location(&self) -> lexer::SourceLocation {
+        match self {
+            Node::Ident(_, location) | Node::Str(_, location) | Node::Interpolation(_, location)
+            | Node::Int(_, location) | Node::Float(_, location) | Node::Char(_, location) => {
+                *location
+            },
+            Node::Nop => lexer::SourceLocation::default(),
+            Node::Comparison { op, .. } => op.1,
+            Node::BinaryExpr { op, .. } => op.1,
+            Node::Unary { op, .. } => op.1,
+            Node::Function { name, .. } => name.1,
+            Node::Return { expr } => expr.1,
+            Node::Call { name, .. } => name.1,
+            Node::Variable { name, .. } => name.1,
+            Node::If { test, .. } => test.1,
+            Node::While { test, .. } => test.1,
+            Node::For { variable, .. } => variable.1,
+            Node::Block { body } => body.1,
+            Node::Break { location, .. } | Node::Continue { location, .. } => *location,
+            Node::Assign { target, .. } => target.1,
+        }
     }
 }
 
-fn expected(expected: &str, got: &Token, location: &lexer::SourceLocation) {
-    println!("{}:{}: [Expected a {} but got {:?}", location.0, location.1, expected, got);
-    process::exit(1);
+type Spanned = (Token, lexer::SourceLocation);
+
+/// Deepest nesting of expressions and blocks the parser accepts. Each
+/// parenthesis, prefix operator, binary operator, call, interpolation,
+/// block and `else if` is one level. The later stages recurse over the
+/// syntax tree too, so this bounds their stack use as well.
+const MAX_NESTING: usize = 64;
+
+/// Returns the depth one level inside `depth`, failing past `MAX_NESTING`.
+fn nest(depth: usize, location: lexer::SourceLocation) -> Result<usize, Diagnostic> {
+    if depth == MAX_NESTING {
+        return Err(Diagnostic::new(format!("nesting exceeds the limit of {} levels", MAX_NESTING), location));
+    }
+    Ok(depth + 1)
 }
 
-fn parse_expr(expr: &Vec<(Token, lexer::SourceLocation)>) -> Node {
-    if expr.len() == 1 {
-        // value
-        match &expr[0].0 {
-            Token::Int(integer) => {
-                return Node::Int(integer.clone(), expr[0].1.clone());
-            },
-            Token::Str(string) => {
-                return Node::Str(string.clone(), expr[0].1.clone());
-            },
-            Token::Ident(ident) => {
-                return Node::Ident(ident.clone(), expr[0].1.clone());
-            },
-            _ => {},
-        }
-    } else if let (Token::Ident(ident), ident_location) = &expr[0] {
-        // Function call
-        let mut index = 1;
-        if &expr[index].0 != &Token::OpenParen {
-            expected("'('", &expr[index].0, &expr[index].1);
+fn expected(expected: &str, got: &Token, location: &lexer::SourceLocation) -> Diagnostic {
+    Diagnostic::new(format!("expected {} but got {:?}", expected, got), *location)
+}
+
+/// Returns the token at `index`, or an error pointing past the last token.
+fn token_at(tokens: &[Spanned], index: usize) -> Result<&Spanned, Diagnostic> {
+    tokens.get(index).ok_or_else(|| {
+        let location = tokens.last().map(|token| token.1).unwrap_or_default();
+        Diagnostic::new(String::from("unexpected end of input"), location)
+    })
+}
+
+fn expect_token(tokens: &[Spanned], index: usize, token: Token, name: &str) -> Result<(), Diagnostic> {
+    let got = token_at(tokens, index)?;
+    if got.0 != token {
+        return Err(expected(name, &got.0, &got.1));
+    }
+    Ok(())
+}
+
+/// Returns the tokens up to the `}` matching an already consumed `{`,
+/// leaving `index` on that `}`.
+fn collect_body<'t>(tokens: &'t [Spanned], index: &mut usize) -> Result<&'t [Spanned], Diagnostic> {
+    let start = *index;
+    let mut indentation = 0;
+    while token_at(tokens, *index)?.0 != Token::CloseBrace || indentation != 0 {
+        if tokens[*index].0 == Token::OpenBrace {
+            indentation += 1;
+        } else if tokens[*index].0 == Token::CloseBrace {
+            indentation -= 1;
         }
-        index += 1;
-        let mut parameter: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-        let parameters_location = expr[index].1.clone();
-        let mut parameters: Vec<Node> = Vec::new();
-        let mut indentation = 0;
-        while (&expr[index].0 != &Token::CloseParen && indentation == 0) || indentation != 0 {
-            if &expr[index].0 == &Token::OpenParen {
-                indentation += 1;
-            } else if &expr[index].0 == &Token::CloseParen {
-                indentation -= 1;
-            }
-            if &expr[index].0 == &Token::Comma {
-                parameters.push(parse_expr(&parameter));
-                parameter = Vec::new();
-            } else {
-                parameter.push(expr[index].clone());
-            }
-            index += 1;
+        *index += 1;
+    }
+    Ok(&tokens[start..*index])
+}
+
+/// Returns the tokens up to the end of the line, leaving `index` on the newline.
+fn collect_line<'t>(tokens: &'t [Spanned], index: &mut usize) -> &'t [Spanned] {
+    let start = *index;
+    while *index < tokens.len() && tokens[*index].0 != Token::Newline {
+        *index += 1;
+    }
+    &tokens[start..*index]
+}
+
+/// Parses the embedded expressions of an interpolated string literal.
+fn parse_interpolation(
+    fragments: &[lexer::Fragment],
+    location: lexer::SourceLocation,
+    depth: usize,
+) -> Result<Node, Diagnostic> {
+    let mut parts = Vec::with_capacity(fragments.len());
+    for fragment in fragments {
+        parts.push(match fragment {
+            lexer::Fragment::Text(text) => Node::Str(text.clone(), location),
+            lexer::Fragment::Expr(tokens) => {
+                let tokens = tokens.iter()
+                    .map(|token| (token.token.clone(), token.span.start))
+                    .collect::<Vec<Spanned>>();
+                parse_expr(&tokens, depth)?
+            },
+        });
+    }
+    Ok(Node::Interpolation(parts, location))
+}
+
+/// How tightly a binary operator binds, or `None` for any other token.
+/// The table follows Rust: bitwise operators bind tighter than
+/// comparisons, and `&&` tighter than `||`.
+fn precedence(token: &Token) -> Option<u8> {
+    match token {
+        Token::Operator(op) => match op {
+            lexer::Operator::Mul | lexer::Operator::Div | lexer::Operator::Mod => Some(10),
+            lexer::Operator::Add | lexer::Operator::Sub => Some(9),
+            lexer::Operator::Shl | lexer::Operator::Shr => Some(8),
+            lexer::Operator::And => Some(7),
+            lexer::Operator::Xor => Some(6),
+            lexer::Operator::Or => Some(5),
+            lexer::Operator::LogicalAnd => Some(3),
+            lexer::Operator::LogicalOr => Some(2),
+            lexer::Operator::Not | lexer::Operator::BitNot => None,
+        },
+        Token::Comparison(_) => Some(4),
+        _ => None,
+    }
+}
+
+fn unclosed(open: lexer::SourceLocation) -> Diagnostic {
+    Diagnostic::new(String::from("this `(` is never closed"), open)
+}
+
+/// Parses `name(arguments)` with `index` on the opening parenthesis,
+/// leaving it after the closing one.
+fn parse_call(
+    tokens: &[Spanned],
+    index: &mut usize,
+    name: (Symbol, lexer::SourceLocation),
+    depth: usize,
+) -> Result<Node, Diagnostic> {
+    let open = tokens[*index].1;
+    *index += 1;
+    let parameters_location = tokens.get(*index).map_or(open, |token| token.1);
+    let mut parameters: Vec<Node> = Vec::new();
+    loop {
+        match tokens.get(*index) {
+            Some((Token::CloseParen, _)) => break,
+            Some(_) => {},
+            None => return Err(unclosed(open)),
         }
-        if parameter.len() != 0 {
-            parameters.push(parse_expr(&parameter));
+        parameters.push(parse_binary(tokens, index, 0, depth)?);
+        match tokens.get(*index) {
+            Some((Token::Comma, _)) => *index += 1,
+            Some((Token::CloseParen, _)) => {},
+            Some((token, location)) => return Err(expected("',' or ')'", token, location)),
+            None => return Err(unclosed(open)),
         }
-        return Node::Call {
-            name: (ident.clone(), ident_location.clone()),
-            parameters: (parameters, parameters_location),
-        };
-    } else {
-        // Binary expression
-        let mut lexpr: (Vec<(Token, lexer::SourceLocation)>, lexer::SourceLocation) = (Vec::new(), (0, 0));
-        let mut rexpr: (Vec<(Token, lexer::SourceLocation)>, lexer::SourceLocation) = (Vec::new(), (0, 0));
-        let mut operator = (lexer::Operator::And, (0, 0));
-        let mut index = 0;
-
-        // iterate trough right expression
-        let mut operator_found = false;
-        while operator_found == false {
-            match &expr[index].0 {
-                Token::Operator(op) => {
-                    operator = (op.clone(), expr[index].1);
-                    operator_found = true;
-                },
-                _ => {
-                    rexpr.0.push(expr[index].clone());
-                },
+    }
+    *index += 1;
+    Ok(Node::Call {
+        name,
+        parameters: (parameters, parameters_location),
+    })
+}
+
+/// Parses a literal, a variable, a call or a parenthesized expression.
+fn parse_operand(tokens: &[Spanned], index: &mut usize, depth: usize) -> Result<Node, Diagnostic> {
+    let (token, location) = token_at(tokens, *index)?;
+    *index += 1;
+    match token {
+        Token::Int(integer) => Ok(Node::Int(*integer, *location)),
+        Token::Float(float) => Ok(Node::Float(*float, *location)),
+        Token::Str(string) => Ok(Node::Str(string.clone(), *location)),
+        Token::Interpolation(fragments) => parse_interpolation(fragments, *location, nest(depth, *location)?),
+        Token::Char(character) => Ok(Node::Char(*character, *location)),
+        Token::Ident(ident) => match tokens.get(*index) {
+            Some((Token::OpenParen, _)) => {
+                parse_call(tokens, index, (ident.clone(), *location), nest(depth, *location)?)
+            },
+            _ => Ok(Node::Ident(ident.clone(), *location)),
+        },
+        Token::OpenParen => {
+            let expr = parse_binary(tokens, index, 0, nest(depth, *location)?)?;
+            match tokens.get(*index) {
+                Some((Token::CloseParen, _)) => *index += 1,
+                Some((token, location)) => return Err(expected("')'", token, location)),
+                None => return Err(unclosed(*location)),
             }
-            index += 1;
-        }
+            Ok(expr)
+        },
+        token => Err(expected("an expression", token, location)),
+    }
+}
 
-        // iterate trough left expression
-        while index < expr.len() {
-            lexpr.0.push(expr[index].clone());
-            index += 1;
-        }
-        return Node::BinaryExpr {
-            lexpr: (Box::new(parse_expr(&lexpr.0)), lexpr.1),
-            rexpr: (Box::new(parse_expr(&rexpr.0)), rexpr.1),
-            op: operator,
-        };
+/// Whether `op` may start an expression: `-x`, `!x`, `~x`, `&x` and `*x`.
+fn is_prefix(op: &lexer::Operator) -> bool {
+    matches!(
+        op,
+        lexer::Operator::Sub | lexer::Operator::Not | lexer::Operator::BitNot
+        | lexer::Operator::And | lexer::Operator::Mul,
+    )
+}
+
+/// Parses prefix operators, which bind tighter than any binary operator.
+fn parse_unary(tokens: &[Spanned], index: &mut usize, depth: usize) -> Result<Node, Diagnostic> {
+    match token_at(tokens, *index)? {
+        (Token::Operator(op), location) if is_prefix(op) => {
+            *index += 1;
+            let expr_location = token_at(tokens, *index)?.1;
+            Ok(Node::Unary {
+                op: (*op, *location),
+                expr: (Box::new(parse_unary(tokens, index, nest(depth, *location)?)?), expr_location),
+            })
+        },
+        _ => parse_operand(tokens, index, depth),
     }
-    return Node::Int(0, (0, 0));
 }
 
-fn parse_condition(condition: &Vec<(Token, lexer::SourceLocation)>) -> Node {
-    // NOTE: Implement multiple conditions later
-    let mut lexpr: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-    let lexpr_location = condition[0].1.clone();
-    let mut rexpr: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-    let mut rexpr_location = (0, 0);
-    let mut index = 0;
-    let mut operator = lexer::Comparison::Nop;
-    let mut op_location = (0, 0);
-    while operator == lexer::Comparison::Nop {
-        match condition[index].0 {
+/// Parses operators binding at least as tightly as `min_precedence` by
+/// precedence climbing. Operands of equal precedence group to the left, so
+/// `a - b - c` is `(a - b) - c`; comparisons do not chain at all.
+fn parse_binary(
+    tokens: &[Spanned],
+    index: &mut usize,
+    min_precedence: u8,
+    mut depth: usize,
+) -> Result<Node, Diagnostic> {
+    let lexpr_location = token_at(tokens, *index)?.1;
+    let mut lexpr = parse_unary(tokens, index, depth)?;
+    let mut compared = false;
+    while let Some((token, location)) = tokens.get(*index) {
+        let precedence = match precedence(token) {
+            Some(precedence) if precedence >= min_precedence => precedence,
+            _ => break,
+        };
+        // every operator puts the operands so far one level deeper
+        depth = nest(depth, *location)?;
+        *index += 1;
+        let rexpr_location = match tokens.get(*index) {
+            Some(operand) => operand.1,
+            None => return Err(Diagnostic::new(String::from("expected an expression after the operator"), *location)),
+        };
+        let rexpr = parse_binary(tokens, index, precedence + 1, depth)?;
+        lexpr = match token {
+            Token::Comparison(_) if compared => {
+                return Err(Diagnostic::new(String::from("comparison operators cannot be chained"), *location));
+            },
             Token::Comparison(op) => {
-                operator = op;
-                op_location = condition[index].1.clone();
+                compared = true;
+                Node::Comparison {
+                    lexpr: (Box::new(lexpr), lexpr_location),
+                    rexpr: (Box::new(rexpr), rexpr_location),
+                    op: (*op, *location),
+                }
             },
-            _ => {
-                lexpr.push(condition[index].clone());
+            Token::Operator(op) => {
+                compared = false;
+                Node::BinaryExpr {
+                    lexpr: (Box::new(lexpr), lexpr_location),
+                    rexpr: (Box::new(rexpr), rexpr_location),
+                    op: (*op, *location),
+                }
             },
-        }
-        index += 1;
+            _ => unreachable!("only operators have a precedence"),
+        };
     }
-    rexpr_location = condition[index].1.clone();
-    while index < condition.len() {
-        rexpr.push(condition[index].clone());
-        index += 1;
+    Ok(lexpr)
+}
+
+fn parse_expr(expr: &[Spanned], depth: usize) -> Result<Node, Diagnostic> {
+    if expr.is_empty() {
+        return Err(Diagnostic::new(String::from("expected an expression"), lexer::SourceLocation::default()));
+    }
+    let mut index = 0;
+    let node = parse_binary(expr, &mut index, 0, depth)?;
+    if let Some((token, location)) = expr.get(index) {
+        return Err(expected("end of expression", token, location));
+    }
+    Ok(node)
+}
+
+/// Parses a type such as `int` or `**char`, leaving `index` after it.
+fn parse_type(tokens: &[Spanned], index: &mut usize) -> Result<(lexer::Type, lexer::SourceLocation), Diagnostic> {
+    let location = token_at(tokens, *index)?.1;
+    let mut pointers = 0;
+    while token_at(tokens, *index)?.0 == Token::Operator(lexer::Operator::Mul) {
+        pointers += 1;
+        *index += 1;
+    }
+    let mut typeid = match token_at(tokens, *index)? {
+        (Token::Type(lexer::Type::Void), location) if pointers > 0 => {
+            return Err(Diagnostic::new(String::from("pointers to `void` are not supported"), *location));
+        },
+        (Token::Type(typeid), _) => typeid.clone(),
+        (token, location) => return Err(expected("type", token, location)),
+    };
+    *index += 1;
+    for _ in 0..pointers {
+        typeid = lexer::Type::Pointer(Box::new(typeid));
+    }
+    Ok((typeid, location))
+}
+
+/// Parses the body of a block with `index` on its `{`, leaving it on the matching `}`.
+fn parse_block(
+    tokens: &[Spanned],
+    index: &mut usize,
+    depth: usize,
+) -> Result<(Vec<Node>, lexer::SourceLocation), Diagnostic> {
+    let depth = nest(depth, tokens[*index].1)?;
+    *index += 1;
+    let location = token_at(tokens, *index)?.1;
+    let body = collect_body(tokens, index)?;
+    Ok((parse_items(body, depth)?, location))
+}
+
+/// Parses the condition after `if` or `while`, with `index` on the keyword,
+/// leaving it on the `{` opening the body. Any `int` expression works as a
+/// condition; it holds when it is not zero.
+fn parse_test(
+    tokens: &[Spanned],
+    index: &mut usize,
+    depth: usize,
+) -> Result<(Box<Node>, lexer::SourceLocation), Diagnostic> {
+    *index += 1;
+    let condition_start = *index;
+    let condition_location = token_at(tokens, *index)?.1;
+    while token_at(tokens, *index)?.0 != Token::OpenBrace {
+        *index += 1;
+    }
+    let condition = &tokens[condition_start..*index];
+    if condition.is_empty() {
+        return Err(Diagnostic::new(String::from("expected a condition"), condition_location));
+    }
+    Ok((Box::new(parse_expr(condition, depth)?), condition_location))
+}
+
+/// Parses `if test { ... }` and any `else if` and `else` branches after it,
+/// with `index` on `if`, leaving it on the last `}`. An `else if` becomes
+/// an else body holding just the nested `Node::If`.
+fn parse_if(tokens: &[Spanned], index: &mut usize, depth: usize) -> Result<Node, Diagnostic> {
+    let test = parse_test(tokens, index, depth)?;
+    let then_body = parse_block(tokens, index, depth)?;
+
+    // `else` may start the line after the closing brace
+    let mut next = *index + 1;
+    while tokens.get(next).is_some_and(|token| token.0 == Token::Newline) {
+        next += 1;
+    }
+    let else_body = match tokens.get(next) {
+        Some((Token::Keyword(lexer::Keyword::Else), _)) => {
+            *index = next + 1;
+            match token_at(tokens, *index)? {
+                (Token::Keyword(lexer::Keyword::If), location) => {
+                    let location = *location;
+                    (vec![parse_if(tokens, index, nest(depth, location)?)?], location)
+                },
+                (Token::OpenBrace, _) => parse_block(tokens, index, depth)?,
+                (token, location) => return Err(expected("'{' or 'if'", token, location)),
+            }
+        },
+        _ => (Vec::new(), tokens[*index].1),
+    };
+    Ok(Node::If {
+        test,
+        then_body,
+        else_body,
+    })
+}
+
+/// Parses a `while` or `for` loop with `index` on its keyword, leaving it
+/// on the closing `}`.
+fn parse_loop(
+    tokens: &[Spanned],
+    index: &mut usize,
+    label: Option<(Symbol, lexer::SourceLocation)>,
+    depth: usize,
+) -> Result<Node, Diagnostic> {
+    match token_at(tokens, *index)? {
+        (Token::Keyword(lexer::Keyword::While), _) => {
+            let test = parse_test(tokens, index, depth)?;
+            Ok(Node::While {
+                label,
+                test,
+                body: parse_block(tokens, index, depth)?,
+            })
+        },
+        (Token::Keyword(lexer::Keyword::For), _) => parse_for(tokens, index, label, depth),
+        (token, location) => Err(expected("a loop after the label", token, location)),
     }
-    return Node::Comparison {
-        rexpr: (Box::new(parse_expr(&rexpr)), rexpr_location),
-        lexpr: (Box::new(parse_expr(&lexpr)), lexpr_location),
-        op: (operator, op_location),
+}
+
+/// Parses `for variable in start..end { ... }` with `index` on `for`,
+/// leaving it on the closing `}`.
+fn parse_for(
+    tokens: &[Spanned],
+    index: &mut usize,
+    label: Option<(Symbol, lexer::SourceLocation)>,
+    depth: usize,
+) -> Result<Node, Diagnostic> {
+    *index += 1;
+    let variable = match token_at(tokens, *index)? {
+        (Token::Ident(ident), location) => (ident.clone(), *location),
+        (token, location) => return Err(expected("identifier", token, location)),
     };
+    *index += 1;
+    expect_token(tokens, *index, Token::Keyword(lexer::Keyword::In), "'in'")?;
+    *index += 1;
+    let range_start = *index;
+    while token_at(tokens, *index)?.0 != Token::OpenBrace {
+        *index += 1;
+    }
+    let range = &tokens[range_start..*index];
+    let dots = match range.iter().position(|token| matches!(token.0, Token::DotDot | Token::DotDotEqual)) {
+        Some(dots) => dots,
+        None => return Err(Diagnostic::new(String::from("expected a range such as `0..n`"), tokens[range_start].1)),
+    };
+    let (start, end) = (&range[..dots], &range[dots + 1..]);
+    if start.is_empty() || end.is_empty() {
+        return Err(Diagnostic::new(String::from("a range needs both a start and an end"), range[dots].1));
+    }
+    Ok(Node::For {
+        label,
+        variable,
+        start: (Box::new(parse_expr(start, depth)?), start[0].1),
+        end: (Box::new(parse_expr(end, depth)?), end[0].1),
+        inclusive: range[dots].0 == Token::DotDotEqual,
+        body: parse_block(tokens, index, depth)?,
+    })
 }
 
-pub fn build_ast(tokens: Vec<(lexer::Token, lexer::SourceLocation)>) -> Vec<Node> {
+/// Parses `break` or `continue` and its optional label with `index` on the
+/// keyword, leaving it on the end of the line.
+fn parse_jump(tokens: &[Spanned], index: &mut usize) -> Result<Node, Diagnostic> {
+    let (keyword, location) = tokens[*index].clone();
+    *index += 1;
+    let label = match tokens.get(*index) {
+        Some((Token::Label(label), location)) => {
+            *index += 1;
+            Some((label.clone(), *location))
+        },
+        _ => None,
+    };
+    if let Some((token, location)) = collect_line(tokens, index).first() {
+        return Err(expected("end of line", token, location));
+    }
+    Ok(match keyword {
+        Token::Keyword(lexer::Keyword::Break) => Node::Break { label, location },
+        _ => Node::Continue { label, location },
+    })
+}
+
+/// Parses a line holding either an expression or an assignment.
+fn parse_statement(line: &[Spanned], depth: usize) -> Result<Node, Diagnostic> {
+    let equal = match line.iter().position(|token| token.0 == Token::Equal) {
+        Some(equal) => equal,
+        None => return parse_expr(line, depth),
+    };
+    let (target, expr) = (&line[..equal], &line[equal + 1..]);
+    let (target_location, expr_location) = match (target.first(), expr.first()) {
+        (Some(target), Some(expr)) => (target.1, expr.1),
+        (None, _) => return Err(Diagnostic::new(String::from("expected something to assign to"), line[equal].1)),
+        (_, None) => return Err(Diagnostic::new(String::from("expected an expression"), line[equal].1)),
+    };
+    Ok(Node::Assign {
+        target: (Box::new(parse_expr(target, depth)?), target_location),
+        expr: (Box::new(parse_expr(expr, depth)?), expr_location),
+    })
+}
+
+fn dangling_docs(docs: &[Spanned]) -> Result<(), Diagnostic> {
+    match docs.first() {
+        Some((_, location)) => Err(Diagnostic::new(
+            String::from("doc comment must be followed by `fn` or `let`"),
+            *location,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Takes the doc comments collected so far for the item being parsed.
+fn take_docs(docs: &mut Vec<Spanned>) -> Vec<Symbol> {
+    docs.drain(..)
+        .filter_map(|(token, _)| match token {
+            Token::DocComment(text) => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn build_ast(tokens: &[(lexer::Token, lexer::SourceLocation)]) -> Result<Vec<Node>, Diagnostic> {
+    parse_items(tokens, 0)
+}
+
+/// Parses the statements and items of a block nested `depth` levels deep.
+fn parse_items(tokens: &[Spanned], depth: usize) -> Result<Vec<Node>, Diagnostic> {
     let mut index = 0;
     let mut ast: Vec<Node> = Vec::new();
+    let mut docs: Vec<Spanned> = Vec::new();
     while index < tokens.len() {
         match &tokens[index].0 {
+            Token::DocComment(_) => docs.push(tokens[index].clone()),
             Token::Keyword(lexer::Keyword::If) => {
+                dangling_docs(&docs)?;
+                ast.push(parse_if(tokens, &mut index, depth)?);
+            },
+            Token::Keyword(lexer::Keyword::While | lexer::Keyword::For) => {
+                dangling_docs(&docs)?;
+                ast.push(parse_loop(tokens, &mut index, None, depth)?);
+            },
+            Token::Label(label) => {
+                dangling_docs(&docs)?;
+                let label = (label.clone(), tokens[index].1);
                 index += 1;
-                let mut condition: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-                let condition_location = tokens[index].1;
-                while &tokens[index].0 != &Token::OpenBrace {
-                    condition.push(tokens[index].clone());
-                    index += 1;
-                }
+                expect_token(tokens, index, Token::Colon, "':'")?;
                 index += 1;
-
-                let mut body: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-                let mut indentation = 0;
-                while (&tokens[index].0 != &Token::CloseBrace && indentation == 0) || indentation != 0 {
-                    if &tokens[index].0 == &Token::OpenBrace {
-                        indentation += 1;
-                    } else if &tokens[index].0 == &Token::CloseBrace {
-                        indentation -= 1;
-                    }
-                    body.push(tokens[index].clone());
-                    index += 1;
-                }
-                if &tokens[index].0 != &Token::Keyword(lexer::Keyword::Else) {
-                    ast.push(Node::If {
-                        test: (Box::new(parse_condition(&condition)), condition_location),
-                        then_body: (build_ast(body), (0, 0)),
-                        else_body: (vec![Node::Nop], (0, 0)),
-                    });
-                }
+                ast.push(parse_loop(tokens, &mut index, Some(label), depth)?);
+            },
+            Token::Keyword(lexer::Keyword::Break | lexer::Keyword::Continue) => {
+                dangling_docs(&docs)?;
+                ast.push(parse_jump(tokens, &mut index)?);
+            },
+            Token::Keyword(lexer::Keyword::Else) => {
+                return Err(Diagnostic::new(
+                    String::from("`else` must follow the `}` of an `if`"),
+                    tokens[index].1,
+                ));
             },
             Token::Keyword(lexer::Keyword::Let) => {
                 index += 1;
-                let ident = match &tokens[index].0 {
-                    Token::Ident(identifier) => (identifier.clone(), tokens[index].1.clone()),
-                    _ => {
-                        expected("identifier", &tokens[index].0, &tokens[index].1);
-                        process::exit(1);
-                    },
+                let ident = match token_at(tokens, index)? {
+                    (Token::Ident(identifier), location) => (identifier.clone(), *location),
+                    (token, location) => return Err(expected("identifier", token, location)),
                 };
                 index += 1;
-                if &tokens[index].0 != &Token::Colon {
-                    expected("':'", &tokens[index].0, &tokens[index].1);
-                }
-                index += 1;
-                let var_type = match &tokens[index].0 {
-                    Token::Type(typeid) => (typeid.clone(), tokens[index].1.clone()),
-                    _ => {
-                        expected("identifier", &tokens[index].0, &tokens[index].1);
-                        process::exit(1);
-                    },
-                };
+                expect_token(tokens, index, Token::Colon, "':'")?;
                 index += 1;
-                if &tokens[index].0 != &Token::Equal {
-                    expected("'='", &tokens[index].0, &tokens[index].1);
-                }
+                let var_type = parse_type(tokens, &mut index)?;
+                expect_token(tokens, index, Token::Equal, "'='")?;
                 index += 1;
-                let mut expression: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-                let expr_location = tokens[index].1.clone();
-                while &tokens[index].0 != &Token::Newline {
-                    expression.push(tokens[index].clone());
-                    index += 1;
+                let expr_location = token_at(tokens, index)?.1;
+                let expression = collect_line(tokens, &mut index);
+                if expression.is_empty() {
+                    return Err(Diagnostic::new(String::from("expected an expression"), expr_location));
                 }
                 ast.push(Node::Variable {
                     name: ident,
                     var_type,
-                    expr: (Box::new(parse_expr(&expression)), expr_location),
+                    expr: (Box::new(parse_expr(expression, depth)?), expr_location),
+                    docs: take_docs(&mut docs),
                 });
             },
             Token::Keyword(lexer::Keyword::Return) => {
+                dangling_docs(&docs)?;
+                let return_location = tokens[index].1;
                 index += 1;
-                let mut expression: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-                let expr_location = tokens[index].1.clone();
-                while &tokens[index].0 != &Token::Newline {
-                    expression.push(tokens[index].clone());
-                    index += 1;
+                let expr_location = tokens.get(index).map(|token| token.1).unwrap_or(return_location);
+                let expression = collect_line(tokens, &mut index);
+                if expression.is_empty() {
+                    return Err(Diagnostic::new(String::from("expected a value to return"), return_location));
                 }
                 ast.push(Node::Return {
-                    expr: (Box::new(parse_expr(&expression)), expr_location),
+                    expr: (Box::new(parse_expr(expression, depth)?), expr_location),
                 });
             },
             Token::Keyword(lexer::Keyword::Function) => {
                 index += 1;
-                let mut function_name: (String, lexer::SourceLocation) = (String::new(), (0, 0));
-                let mut function_type: (lexer::Type, lexer::SourceLocation) = (lexer::Type::Void, (0, 0));
-                let mut function_parameters: (Vec<(String, lexer::Type, lexer::SourceLocation)>, lexer::SourceLocation)
-                    = (Vec::new(), (0, 0));
-                match &tokens[index].0 {
-                    Token::Ident(ident) => {
-                        function_name = (ident.clone(), tokens[index].1.clone());
-                    },
-                    _ => {
-                        expected("identifier", &tokens[index].0, &tokens[index].1);
-                    },
-                }
+                let function_name = match token_at(tokens, index)? {
+                    (Token::Ident(ident), location) => (ident.clone(), *location),
+                    (token, location) => return Err(expected("identifier", token, location)),
+                };
                 index += 1;
-                if &tokens[index].0 != &Token::OpenParen {
-                    expected("'('", &tokens[index].0, &tokens[index].1);
-                }
+                expect_token(tokens, index, Token::OpenParen, "'('")?;
                 index += 1;
-                function_parameters.1 = tokens[index].1.clone();
-                while &tokens[index].0 != &Token::CloseParen {
-                    match &tokens[index].0 {
-                        Token::Type(typeid) => {
-                            index += 1;
-                            match &tokens[index].0 {
-                                Token::Ident(ident) => {
-                                    function_parameters.0.push((ident.clone(), typeid.clone(), tokens[index].1.clone()));
-                                    if &tokens[index + 1].0 == &Token::Comma {
-                                        index += 1;
-                                    }
-                                },
-                                _ => {
-                                    expected("type", &tokens[index].0, &tokens[index].1);
-                                },
+                let mut function_parameters: (Vec<(Symbol, lexer::Type, lexer::SourceLocation)>, lexer::SourceLocation)
+                    = (Vec::new(), token_at(tokens, index)?.1);
+                while token_at(tokens, index)?.0 != Token::CloseParen {
+                    let (typeid, _) = parse_type(tokens, &mut index)?;
+                    match token_at(tokens, index)? {
+                        (Token::Ident(ident), location) => {
+                            function_parameters.0.push((ident.clone(), typeid, *location));
+                            if token_at(tokens, index + 1)?.0 == Token::Comma {
+                                index += 1;
                             }
                         },
-                        _ => {
-                            expected("type", &tokens[index].0, &tokens[index].1);
-                        },
+                        (token, location) => return Err(expected("identifier", token, location)),
                     }
                     index += 1;
                 }
                 index += 1;
-                if &tokens[index].0 != &Token::Colon {
-                    expected("':'", &tokens[index].0, &tokens[index].1);
-                }
-                index += 1;
-                match &tokens[index].0 {
-                    Token::Type(typeid) => {
-                        function_type = (typeid.clone(), tokens[index].1.clone());
-                    },
-                    _ => {
-                        expected("type", &tokens[index].0, &tokens[index].1);
-                    },
-                }
+                expect_token(tokens, index, Token::Colon, "':'")?;
                 index += 1;
-                if &tokens[index].0 != &Token::OpenBrace {
-                    expected("'{{'", &tokens[index].0, &tokens[index].1);
-                }
+                let function_type = parse_type(tokens, &mut index)?;
+                expect_token(tokens, index, Token::OpenBrace, "'{'")?;
                 index += 1;
-                let mut body_tokens: Vec<(Token, lexer::SourceLocation)> = Vec::new();
-                let body_location = tokens[index].1.clone();
-                let mut indentation = 0;
-                while (&tokens[index].0 != &Token::CloseBrace && indentation == 0) || indentation != 0 {
-                    if &tokens[index].0 == &Token::OpenBrace {
-                        indentation += 1;
-                    } else if &tokens[index].0 == &Token::CloseBrace {
-                        indentation -= 1;
-                    }
-                    body_tokens.push(tokens[index].clone());
-                    index += 1;
-                }
+                let body_depth = nest(depth, tokens[index - 1].1)?;
+                let body_location = token_at(tokens, index)?.1;
+                let body_tokens = collect_body(tokens, &mut index)?;
                 ast.push(Node::Function {
-                    body: (build_ast(body_tokens), body_location),
+                    body: (parse_items(body_tokens, body_depth)?, body_location),
                     name: function_name,
                     parameters: function_parameters,
                     return_type: function_type,
+                    docs: take_docs(&mut docs),
                 });
             },
             _ => {
-                let mut expr: Vec<(Token , lexer::SourceLocation)> = Vec::new();
-                while &tokens[index].0 != &Token::Newline {
-                    expr.push(tokens[index].clone());
-                    index += 1;
-                }
-                if expr.len() != 0 {
-                    ast.push(parse_expr(&expr));
+                let line = collect_line(tokens, &mut index);
+                if !line.is_empty() {
+                    dangling_docs(&docs)?;
+                    ast.push(parse_statement(line, depth)?);
                 }
             },
         }
         index += 1;
     }
-    return ast;
+    dangling_docs(&docs)?;
+    Ok(ast)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver;
+
+    /// Parses `source`, returning the messages of the diagnostics.
+    fn errors(source: &str) -> Vec<String> {
+        match driver::parse(source) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.into_iter().map(|diagnostic| diagnostic.message).collect(),
+        }
+    }
+
+    #[test]
+    fn nesting_beyond_the_limit_is_an_error() {
+        let message = format!("nesting exceeds the limit of {} levels", MAX_NESTING);
+        let deep = 50_000;
+        let sources = [
+            format!("print({}1{})\n", "(".repeat(deep), ")".repeat(deep)),
+            format!("print({}1)\n", "-".repeat(deep)),
+            format!("print(1{})\n", " + 1".repeat(deep)),
+            format!("{}print(1)\n{}", "if 1 {\n".repeat(deep), "}\n".repeat(deep)),
+            format!("if 0 {{\n}}{}\n", " else if 0 {\n}".repeat(deep)),
+        ];
+        for source in &sources {
+            assert_eq!(errors(source), [message.as_str()]);
+        }
+    }
+
+    #[test]
+    fn nesting_within_the_limit_parses() {
+        let depth = MAX_NESTING - 2;
+        assert!(errors(&format!("print({}1{})\n", "(".repeat(depth), ")".repeat(depth))).is_empty());
+        assert!(errors(&format!("let x: int = {}1\n", "-".repeat(depth))).is_empty());
+    }
+
+    fn expr_tokens(source: &str) -> Vec<Spanned> {
+        lexer::tokenize(source)
+            .expect("expression lexes")
+            .into_iter()
+            .map(|token| (token.token, token.span.start))
+            .collect::<Vec<Spanned>>()
+    }
+
+    /// Parses the expression `source` and prints it back with every
+    /// operation in parentheses, or returns the diagnostic message.
+    fn grouped(source: &str) -> Result<String, String> {
+        parse_expr(&expr_tokens(source), 0).map(|node| render(&node)).map_err(|diagnostic| diagnostic.message)
+    }
+
+    fn render(node: &Node) -> String {
+        match node {
+            Node::Int(int, _) => int.to_string(),
+            Node::Ident(name, _) => name.to_string(),
+            Node::Unary { op, expr } => format!("({}{})", op.0, render(&expr.0)),
+            Node::BinaryExpr { lexpr, rexpr, op } => format!("({} {} {})", render(&lexpr.0), op.0, render(&rexpr.0)),
+            Node::Comparison { lexpr, rexpr, op } => {
+                format!("({} {:?} {})", render(&lexpr.0), op.0, render(&rexpr.0))
+            },
+            node => panic!("unexpected node in expression: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        let expressions = [
+            ("1 + 2 * 3", "(1 + (2 * 3))"),
+            ("2 * 3 + 1", "((2 * 3) + 1)"),
+            ("(1 + 2) * 3", "((1 + 2) * 3)"),
+            ("a % b - c", "((a % b) - c)"),
+            ("1 << 2 + 3", "(1 << (2 + 3))"),
+            ("a | b ^ c & d", "(a | (b ^ (c & d)))"),
+            ("a & b == c", "((a & b) Equal c)"),
+            ("a < b + 1", "(a Smaller (b + 1))"),
+            ("a == b && c || d", "(((a Equal b) && c) || d)"),
+            ("a || b && c", "(a || (b && c))"),
+            ("-a * b", "((-a) * b)"),
+            ("a * -b", "(a * (-b))"),
+            ("!a && ~b", "((!a) && (~b))"),
+            ("*a + &b", "((*a) + (&b))"),
+        ];
+        for (source, expected) in expressions {
+            assert_eq!(grouped(source).as_deref(), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn operators_of_equal_precedence_group_to_the_left() {
+        let expressions = [
+            ("a - b - c", "((a - b) - c)"),
+            ("a / b / c", "((a / b) / c)"),
+            ("a - b + c", "((a - b) + c)"),
+            ("a * b % c / d", "(((a * b) % c) / d)"),
+            ("1 << 2 >> 3", "((1 << 2) >> 3)"),
+            ("a && b && c", "((a && b) && c)"),
+            ("a - b * c - d", "((a - (b * c)) - d)"),
+        ];
+        for (source, expected) in expressions {
+            assert_eq!(grouped(source).as_deref(), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn operands_keep_their_locations() {
+        match parse_expr(&expr_tokens("a - b - c"), 0) {
+            Ok(Node::BinaryExpr { lexpr, rexpr, op }) => {
+                assert_eq!((lexpr.1.column, op.1.column, rexpr.1.column), (1, 7, 9));
+                assert!(matches!(*lexpr.0, Node::BinaryExpr { .. }));
+            },
+            result => panic!("expected a subtraction, got {:?}", result),
+        }
+    }
 
+    #[test]
+    fn comparisons_do_not_chain() {
+        let message = "comparison operators cannot be chained";
+        for source in ["a < b < c", "a == b == c", "a < b == c", "a < b + 1 < c", "a != b >= c && d"] {
+            assert_eq!(grouped(source), Err(String::from(message)), "{}", source);
+        }
+        assert_eq!(grouped("(a < b) == c").as_deref(), Ok("((a Smaller b) Equal c)"));
+        assert_eq!(grouped("a < b && b < c").as_deref(), Ok("((a Smaller b) && (b Smaller c))"));
+    }
+
+    /// Parses `source`, which must be free of errors.
+    fn parsed(source: &str) -> Vec<Node> {
+        driver::parse(source).expect("source parses").body
+    }
+
+    /// Walks an `if` chain, returning the column of each test and the
+    /// column where the final `else` body starts, if there is one.
+    fn chain(node: &Node) -> (Vec<usize>, Option<usize>) {
+        let mut tests = Vec::new();
+        let mut node = node;
+        loop {
+            match node {
+                Node::If { test, then_body, else_body } => {
+                    assert_eq!(then_body.0.len(), 1, "every branch in these tests holds one statement");
+                    tests.push(test.1.column);
+                    match else_body.0.as_slice() {
+                        [] => return (tests, None),
+                        [nested @ Node::If { .. }] => node = nested,
+                        _ => return (tests, Some(else_body.1.column)),
+                    }
+                },
+                node => panic!("expected an if, got {:?}", node),
+            }
+        }
+    }
+
+    #[test]
+    fn if_else_keeps_both_bodies() {
+        let body = parsed("if a { print(1) } else { print(2) }\nprint(3)\n");
+        assert_eq!(body.len(), 2);
+        assert_eq!(chain(&body[0]), (vec![4], Some(26)));
+        assert!(matches!(&body[1], Node::Call { .. }));
+
+        let body = parsed("if a { print(1) }\nprint(3)\n");
+        assert_eq!(chain(&body[0]), (vec![4], None));
+    }
+
+    #[test]
+    fn else_may_start_the_next_line() {
+        let body = parsed("if a {\n    print(1)\n}\n\nelse {\n    print(2)\n}\n");
+        match body.as_slice() {
+            [Node::If { else_body, .. }] => {
+                assert!(matches!(else_body.0.as_slice(), [Node::Call { .. }]));
+                assert_eq!((else_body.1.line, else_body.1.column), (5, 7));
+            },
+            body => panic!("expected a single if, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn else_if_chains_nest() {
+        let source = "if a { print(1) } else if b { print(2) } else if c { print(3) } else { print(4) }\n";
+        assert_eq!(chain(&parsed(source)[0]), (vec![4, 27, 50], Some(72)));
+        let source = "if a { print(1) } else if b { print(2) }\n";
+        assert_eq!(chain(&parsed(source)[0]), (vec![4, 27], None));
+    }
+
+    #[test]
+    fn else_needs_a_block_or_if() {
+        let messages = errors("if a { print(1) } else print(2)\n");
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("expected '{' or 'if'"), "{}", messages[0]);
+    }
+}
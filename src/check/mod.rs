@@ -0,0 +1,564 @@
+use crate::lexer;
+use crate::ast::Node;
+use crate::diagnostic::Diagnostic;
+use crate::driver::Program;
+use crate::symbol::Symbol;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+
+/// Types as seen by the checker, string literals included.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Ty {
+    Void,
+    Int,
+    Char,
+    Float,
+    Str,
+    Pointer(Box<Ty>),
+}
+
+impl From<&lexer::Type> for Ty {
+    fn from(typeid: &lexer::Type) -> Ty {
+        match typeid {
+            lexer::Type::Void => Ty::Void,
+            lexer::Type::Int => Ty::Int,
+            lexer::Type::Char => Ty::Char,
+            lexer::Type::Float => Ty::Float,
+            lexer::Type::Pointer(typeid) => Ty::Pointer(Box::new(Ty::from(&**typeid))),
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::Void => write!(f, "void"),
+            Ty::Int => write!(f, "int"),
+            Ty::Char => write!(f, "char"),
+            Ty::Float => write!(f, "float"),
+            Ty::Str => write!(f, "str"),
+            Ty::Pointer(ty) => write!(f, "*{}", ty),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub parameters: Vec<Ty>,
+    pub return_type: Ty,
+}
+
+/// A variable in scope.
+struct Binding {
+    ty: Ty,
+    /// For a pointer, the deepest variable it may point to; see `Checker::reach`.
+    reach: usize,
+}
+
+/// Saved state of the enclosing scope while a function body is checked.
+pub struct Frame {
+    scopes: Vec<HashMap<Symbol, Binding>>,
+    loops: Vec<Option<Symbol>>,
+    return_type: Ty,
+    base: usize,
+}
+
+/// Resolves names and computes expression types for a whole program.
+///
+/// The backends reuse it to type expressions while walking a program
+/// that already passed `check`.
+pub struct Checker {
+    functions: HashMap<Symbol, Signature>,
+    scopes: Vec<HashMap<Symbol, Binding>>,
+    /// Labels of the loops around the statement being checked, innermost last.
+    loops: Vec<Option<Symbol>>,
+    return_type: Ty,
+    /// Turns a scope index into the depth of the variables in it. The
+    /// outermost variables of the top level live as long as the program and
+    /// get depth 0; in a function even the parameters die on return, so
+    /// depths start at 1.
+    base: usize,
+    errors: Vec<Diagnostic>,
+}
+
+/// Functions provided by every backend.
+pub const BUILTINS: [&str; 1] = ["print"];
+
+impl Checker {
+    pub fn new(program: &Program) -> Checker {
+        let mut checker = Checker {
+            functions: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            loops: Vec::new(),
+            // the top level returns the exit code of the program
+            return_type: Ty::Int,
+            base: 0,
+            errors: Vec::new(),
+        };
+        for node in &program.body {
+            if let Node::Function { name, parameters, return_type, .. } = node {
+                if BUILTINS.contains(&name.0.as_str()) || checker.functions.contains_key(&name.0) {
+                    checker.error(format!("function `{}` is already defined", name.0), name.1);
+                    continue;
+                }
+                checker.functions.insert(name.0.clone(), Signature {
+                    parameters: parameters.0.iter().map(|(_, typeid, _)| Ty::from(typeid)).collect(),
+                    return_type: Ty::from(&return_type.0),
+                });
+            }
+        }
+        checker
+    }
+
+    fn error(&mut self, message: String, location: lexer::SourceLocation) {
+        self.errors.push(Diagnostic::new(message, location));
+    }
+
+    pub fn signature(&self, name: &Symbol) -> Option<&Signature> {
+        self.functions.get(name)
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    pub fn declare(&mut self, name: Symbol, ty: Ty, location: lexer::SourceLocation) {
+        self.bind(name, ty, 0, location);
+    }
+
+    fn bind(&mut self, name: Symbol, ty: Ty, reach: usize, location: lexer::SourceLocation) {
+        let scope = self.scopes.last_mut().expect("no scope to declare in");
+        if scope.insert(name.clone(), Binding { ty, reach }).is_some() {
+            self.error(format!("`{}` is already declared in this scope", name), location);
+        }
+    }
+
+    pub fn lookup(&self, name: &Symbol) -> Option<&Ty> {
+        self.binding(name).map(|(_, binding)| &binding.ty)
+    }
+
+    /// Finds the variable `name` together with its depth.
+    fn binding(&self, name: &Symbol) -> Option<(usize, &Binding)> {
+        self.scopes.iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, scope)| scope.get(name).map(|binding| (index + self.base, binding)))
+    }
+
+    /// Returns the depth of the deepest variable whose address the value of
+    /// `node` may hold, or 0 if it holds no address that dies before the
+    /// current function returns. Addresses may only be stored where they
+    /// die with their variable, so reading a pointer never yields a deeper
+    /// address than the one it was read through.
+    fn reach(&self, node: &Node) -> usize {
+        match node {
+            Node::Ident(name, _) => self.binding(name).map_or(0, |(_, binding)| binding.reach),
+            Node::Unary { op: (lexer::Operator::And, _), expr } => match &*expr.0 {
+                Node::Ident(name, _) => self.binding(name).map_or(0, |(depth, _)| depth),
+                place => self.reach(place),
+            },
+            Node::Unary { op: (lexer::Operator::Mul, _), expr } => self.reach(&expr.0),
+            // what a function returns can only lead back to its arguments
+            Node::Call { parameters, .. } => {
+                parameters.0.iter().map(|argument| self.reach(argument)).max().unwrap_or(0)
+            },
+            _ => 0,
+        }
+    }
+
+    /// Enters the body of a function; functions do not see top-level variables.
+    pub fn enter_function(
+        &mut self,
+        parameters: &[(Symbol, lexer::Type, lexer::SourceLocation)],
+        return_type: &lexer::Type,
+    ) -> Frame {
+        let frame = Frame {
+            scopes: mem::replace(&mut self.scopes, vec![HashMap::new()]),
+            loops: mem::take(&mut self.loops),
+            return_type: mem::replace(&mut self.return_type, Ty::from(return_type)),
+            base: mem::replace(&mut self.base, 1),
+        };
+        for (name, typeid, location) in parameters {
+            self.declare(name.clone(), Ty::from(typeid), *location);
+        }
+        frame
+    }
+
+    pub fn leave_function(&mut self, frame: Frame) {
+        self.scopes = frame.scopes;
+        self.loops = frame.loops;
+        self.return_type = frame.return_type;
+        self.base = frame.base;
+    }
+
+    fn expect(&mut self, expected: &Ty, got: &Ty, location: lexer::SourceLocation) {
+        if expected != got {
+            self.error(format!("expected `{}` but got `{}`", expected, got), location);
+        }
+    }
+
+    /// Returns the type of an expression, reporting any errors inside it.
+    pub fn expr(&mut self, node: &Node) -> Ty {
+        match node {
+            Node::Int(_, _) => Ty::Int,
+            Node::Float(_, _) => Ty::Float,
+            Node::Str(_, _) => Ty::Str,
+            Node::Interpolation(parts, _) => {
+                for part in parts {
+                    let ty = self.expr(part);
+                    if !printable(&ty) {
+                        self.error(format!("cannot interpolate a `{}` value", ty), part.location());
+                    }
+                }
+                Ty::Str
+            },
+            Node::Char(_, _) => Ty::Char,
+            Node::Ident(name, location) => match self.lookup(name) {
+                Some(ty) => ty.clone(),
+                None => {
+                    self.error(format!("cannot find variable `{}` in this scope", name), *location);
+                    Ty::Int
+                },
+            },
+            Node::BinaryExpr { lexpr, rexpr, op } => {
+                let left = self.expr(&lexpr.0);
+                let right = self.expr(&rexpr.0);
+                let arithmetic = matches!(
+                    op.0,
+                    lexer::Operator::Add | lexer::Operator::Sub | lexer::Operator::Mul | lexer::Operator::Div,
+                );
+                match (&left, &right) {
+                    (Ty::Int, Ty::Int) => Ty::Int,
+                    (Ty::Float, Ty::Float) if arithmetic => Ty::Float,
+                    _ => {
+                        self.error(format!("cannot apply `{}` to `{}` and `{}`", op.0, left, right), op.1);
+                        if left == Ty::Float { Ty::Float } else { Ty::Int }
+                    },
+                }
+            },
+            Node::Unary { op, expr } => {
+                let ty = self.expr(&expr.0);
+                match (op.0, ty) {
+                    (lexer::Operator::Sub, ty @ (Ty::Int | Ty::Float)) => ty,
+                    (lexer::Operator::Not | lexer::Operator::BitNot, Ty::Int) => Ty::Int,
+                    (lexer::Operator::And, ty) if is_place(&expr.0) => Ty::Pointer(Box::new(ty)),
+                    (lexer::Operator::And, _) => {
+                        self.error(String::from("cannot take the address of a temporary value"), op.1);
+                        Ty::Int
+                    },
+                    (lexer::Operator::Mul, Ty::Pointer(ty)) => *ty,
+                    (lexer::Operator::Mul, ty) => {
+                        self.error(format!("cannot dereference `{}`", ty), op.1);
+                        Ty::Int
+                    },
+                    (op, ty) => {
+                        self.error(format!("cannot apply `{}` to `{}`", op, ty), expr.1);
+                        if ty == Ty::Float { Ty::Float } else { Ty::Int }
+                    },
+                }
+            },
+            Node::Comparison { lexpr, rexpr, op } => {
+                let left = self.expr(&lexpr.0);
+                let right = self.expr(&rexpr.0);
+                if left != right || !matches!(left, Ty::Int | Ty::Char | Ty::Float) {
+                    self.error(format!("cannot compare `{}` with `{}`", left, right), op.1);
+                }
+                Ty::Int
+            },
+            Node::Call { name, parameters } => self.call(name, &parameters.0),
+            _ => {
+                self.error(String::from("expected an expression"), node.location());
+                Ty::Void
+            },
+        }
+    }
+
+    fn call(&mut self, name: &(Symbol, lexer::SourceLocation), arguments: &[Node]) -> Ty {
+        let types = arguments.iter().map(|argument| self.expr(argument)).collect::<Vec<Ty>>();
+        if name.0.as_str() == "print" {
+            if types.len() != 1 {
+                self.error(format!("`print` takes 1 argument but {} were supplied", types.len()), name.1);
+            } else if !printable(&types[0]) {
+                self.error(format!("cannot print a `{}` value", types[0]), arguments[0].location());
+            }
+            return Ty::Void;
+        }
+        let signature = match self.functions.get(&name.0) {
+            Some(signature) => signature.clone(),
+            None => {
+                self.error(format!("cannot find function `{}`", name.0), name.1);
+                return Ty::Void;
+            },
+        };
+        // a callee can store an argument through another one that points to its type
+        for (index, (ty, argument)) in types.iter().zip(arguments).enumerate() {
+            let kept = types.iter().enumerate().any(|(other, container)| other != index && points_to(container, ty));
+            if kept && self.reach(argument) > 0 {
+                let message = format!("`{}` could keep the address of a local variable past its end", name.0);
+                self.error(message, argument.location());
+            }
+        }
+        if signature.parameters.len() != types.len() {
+            self.error(format!(
+                "`{}` takes {} argument(s) but {} were supplied",
+                name.0, signature.parameters.len(), types.len(),
+            ), name.1);
+        } else {
+            for ((expected, got), argument) in signature.parameters.iter().zip(&types).zip(arguments) {
+                self.expect(expected, got, argument.location());
+            }
+        }
+        signature.return_type
+    }
+
+    pub fn block(&mut self, body: &[Node]) {
+        self.push_scope();
+        for node in body {
+            self.statement(node);
+        }
+        self.pop_scope();
+    }
+
+    /// Checks the body of a loop, which `break` and `continue` may leave.
+    fn loop_body(&mut self, label: &Option<(Symbol, lexer::SourceLocation)>, body: &[Node]) {
+        self.loops.push(label.as_ref().map(|label| label.0.clone()));
+        self.block(body);
+        self.loops.pop();
+    }
+
+    fn statement(&mut self, node: &Node) {
+        match node {
+            Node::Nop => {},
+            Node::Variable { name, var_type, expr, .. } => {
+                let ty = self.expr(&expr.0);
+                let expected = Ty::from(&var_type.0);
+                if expected == Ty::Void {
+                    self.error(format!("variable `{}` cannot be `void`", name.0), var_type.1);
+                }
+                self.expect(&expected, &ty, expr.1);
+                let reach = if let Ty::Pointer(_) = expected { self.reach(&expr.0) } else { 0 };
+                self.bind(name.0.clone(), expected, reach, name.1);
+            },
+            Node::Return { expr } => {
+                let ty = self.expr(&expr.0);
+                let expected = self.return_type.clone();
+                self.expect(&expected, &ty, expr.1);
+                if let Ty::Pointer(_) = ty {
+                    if self.reach(&expr.0) > 0 {
+                        self.error(String::from("cannot return the address of a local variable"), expr.1);
+                    }
+                }
+            },
+            Node::If { test, then_body, else_body } => {
+                let ty = self.expr(&test.0);
+                self.expect(&Ty::Int, &ty, test.1);
+                self.block(&then_body.0);
+                self.block(&else_body.0);
+            },
+            Node::While { label, test, body } => {
+                let ty = self.expr(&test.0);
+                self.expect(&Ty::Int, &ty, test.1);
+                self.loop_body(label, &body.0);
+            },
+            Node::For { label, variable, start, end, body, .. } => {
+                for bound in [start, end] {
+                    let ty = self.expr(&bound.0);
+                    self.expect(&Ty::Int, &ty, bound.1);
+                }
+                self.push_scope();
+                self.declare(variable.0.clone(), Ty::Int, variable.1);
+                self.loop_body(label, &body.0);
+                self.pop_scope();
+            },
+            Node::Break { label, location } | Node::Continue { label, location } => {
+                let keyword = if let Node::Break { .. } = node { "break" } else { "continue" };
+                match label {
+                    _ if self.loops.is_empty() => {
+                        self.error(format!("`{}` outside of a loop", keyword), *location);
+                    },
+                    Some(label) if !self.loops.iter().any(|target| target.as_ref() == Some(&label.0)) => {
+                        self.error(format!("use of undeclared label `'{}`", label.0), label.1);
+                    },
+                    _ => {},
+                }
+            },
+            Node::Block { body } => self.block(&body.0),
+            Node::Assign { target, expr } => {
+                let expected = self.expr(&target.0);
+                let ty = self.expr(&expr.0);
+                if is_place(&target.0) {
+                    self.expect(&expected, &ty, expr.1);
+                    if let Ty::Pointer(_) = expected {
+                        // through a pointer, the address may end up anywhere the function can reach
+                        let limit = match &*target.0 {
+                            Node::Ident(name, _) => self.binding(name).map_or(0, |(_, binding)| binding.reach),
+                            _ => 0,
+                        };
+                        if self.reach(&expr.0) > limit {
+                            self.error(String::from("the address of a local variable would outlive it here"), expr.1);
+                        }
+                    }
+                } else {
+                    self.error(String::from("cannot assign to a temporary value"), target.1);
+                }
+            },
+            Node::Function { name, .. } => {
+                self.error(format!("function `{}` must be declared at the top level", name.0), name.1);
+            },
+            _ => {
+                self.expr(node);
+            },
+        }
+    }
+
+    fn function(&mut self, node: &Node) {
+        if let Node::Function { name, parameters, return_type, body, .. } = node {
+            let frame = self.enter_function(&parameters.0, &return_type.0);
+            self.block(&body.0);
+            if return_type.0 != lexer::Type::Void && !returns(&body.0) {
+                self.error(format!("function `{}` does not return a value on every path", name.0), name.1);
+            }
+            self.leave_function(frame);
+        }
+    }
+}
+
+/// Whether `print` and string interpolation accept a value of `ty`.
+fn printable(ty: &Ty) -> bool {
+    matches!(ty, Ty::Int | Ty::Char | Ty::Float | Ty::Str)
+}
+
+/// Whether a value of type `container` leads through pointers to a place
+/// holding a `ty`.
+fn points_to(container: &Ty, ty: &Ty) -> bool {
+    match container {
+        Ty::Pointer(inner) => **inner == *ty || points_to(inner, ty),
+        _ => false,
+    }
+}
+
+/// Whether `node` names a storage location, so `&node` points into it.
+fn is_place(node: &Node) -> bool {
+    matches!(node, Node::Ident(..) | Node::Unary { op: (lexer::Operator::Mul, _), .. })
+}
+
+/// Whether executing `body` always ends in a `return`.
+pub fn returns(body: &[Node]) -> bool {
+    match body.last() {
+        Some(Node::Return { .. }) => true,
+        Some(Node::If { then_body, else_body, .. }) => returns(&then_body.0) && returns(&else_body.0),
+        _ => false,
+    }
+}
+
+/// Runs name resolution and type checking over the whole program.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(program);
+    for node in &program.body {
+        match node {
+            Node::Function { .. } => checker.function(node),
+            _ => checker.statement(node),
+        }
+    }
+    checker.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+    use crate::driver;
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = driver::parse(source).expect("test program parses");
+        check(&program).into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    #[test]
+    fn returning_the_address_of_a_local_is_rejected() {
+        let message = "cannot return the address of a local variable";
+        assert_eq!(errors("fn f(): *int {\n    let x: int = 5\n    return &x\n}\n"), [message]);
+        assert_eq!(errors("fn f(int x): *int {\n    let p: *int = &x\n    return p\n}\n"), [message]);
+        let through_call = "\
+fn id(*int p): *int {
+    return p
+}
+fn f(): *int {
+    let x: int = 5
+    return id(&x)
+}
+";
+        assert_eq!(errors(through_call), [message]);
+    }
+
+    #[test]
+    fn pointers_from_the_caller_may_be_returned() {
+        let source = "\
+fn id(*int p): *int {
+    return p
+}
+fn first(**int p): *int {
+    return *p
+}
+let x: int = 5
+let p: *int = id(&x)
+print(*first(&p))
+";
+        assert!(errors(source).is_empty());
+    }
+
+    #[test]
+    fn storing_an_address_beyond_its_variable_is_rejected() {
+        let message = "the address of a local variable would outlive it here";
+        let outer = "\
+let x: int = 1
+let p: *int = &x
+if 1 {
+    let y: int = 2
+    p = &y
+}
+";
+        assert_eq!(errors(outer), [message]);
+        let through_pointer = "\
+fn f(**int pp): void {
+    let x: int = 1
+    *pp = &x
+}
+";
+        assert_eq!(errors(through_pointer), [message]);
+        let same_scope = "\
+fn f(): int {
+    let x: int = 1
+    let y: int = 2
+    let p: *int = &x
+    p = &y
+    return *p
+}
+";
+        assert!(errors(same_scope).is_empty());
+    }
+
+    #[test]
+    fn passing_an_address_a_callee_could_keep_is_rejected() {
+        let source = "\
+fn set(**int pp, *int p): void {
+    *pp = p
+}
+fn f(): void {
+    let x: int = 1
+    let p: *int = &x
+    if 1 {
+        let y: int = 2
+        set(&p, &y)
+    }
+}
+";
+        assert_eq!(errors(source), ["`set` could keep the address of a local variable past its end"]);
+    }
+}
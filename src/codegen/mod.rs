@@ -0,0 +1,858 @@
+use crate::lexer;
+use crate::ast::Node;
+use crate::check::{Checker, Ty};
+use crate::driver::{CheckedProgram, SourceMap};
+use crate::interp::MAX_CALL_DEPTH;
+use crate::symbol::Symbol;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+
+const PRELUDE: &str = r#"#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* strings carry their length since literals may contain NUL bytes */
+typedef struct {
+    const char *data;
+    size_t length;
+} jet_str;
+
+/* `jet_format_*` write the text `print` shows for a value into `buffer`
+   and return its length */
+static int jet_format_int(int64_t value, char *buffer) {
+    return sprintf(buffer, "%lld", (long long) value);
+}
+
+static int jet_format_char(uint32_t value, char *buffer) {
+    if (value < 0x80) {
+        buffer[0] = (char) value;
+        return 1;
+    } else if (value < 0x800) {
+        buffer[0] = (char) (0xC0 | (value >> 6));
+        buffer[1] = (char) (0x80 | (value & 0x3F));
+        return 2;
+    } else if (value < 0x10000) {
+        buffer[0] = (char) (0xE0 | (value >> 12));
+        buffer[1] = (char) (0x80 | ((value >> 6) & 0x3F));
+        buffer[2] = (char) (0x80 | (value & 0x3F));
+        return 3;
+    }
+    buffer[0] = (char) (0xF0 | (value >> 18));
+    buffer[1] = (char) (0x80 | ((value >> 12) & 0x3F));
+    buffer[2] = (char) (0x80 | ((value >> 6) & 0x3F));
+    buffer[3] = (char) (0x80 | (value & 0x3F));
+    return 4;
+}
+
+/* the shortest digits that read back as the same value, in fixed notation
+   from 1e-4 up to 1e16 and as `1.5e16` outside, like the interpreter */
+static int jet_format_float(double value, char *buffer) {
+    if (isnan(value)) {
+        return sprintf(buffer, "NaN");
+    }
+    if (isinf(value)) {
+        return sprintf(buffer, value < 0 ? "-inf" : "inf");
+    }
+    if (value == 0) {
+        return sprintf(buffer, signbit(value) ? "-0.0" : "0.0");
+    }
+    char scientific[32];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision, value);
+        if (strtod(scientific, NULL) == value) {
+            break;
+        }
+    }
+
+    char digits[20];
+    int count = 0;
+    int length = 0;
+    const char *cursor = scientific;
+    if (*cursor == '-') {
+        buffer[length++] = '-';
+        cursor++;
+    }
+    for (; *cursor != 'e'; cursor++) {
+        if (*cursor != '.') {
+            digits[count++] = *cursor;
+        }
+    }
+    int exponent = atoi(cursor + 1);
+    double magnitude = value < 0 ? -value : value;
+    if (magnitude >= 1e-4 && magnitude < 1e16) {
+        if (exponent < 0) {
+            buffer[length++] = '0';
+            buffer[length++] = '.';
+            for (int zero = -1; zero > exponent; zero--) {
+                buffer[length++] = '0';
+            }
+            memcpy(buffer + length, digits, count);
+            length += count;
+        } else {
+            for (int digit = 0; digit <= exponent; digit++) {
+                buffer[length++] = digit < count ? digits[digit] : '0';
+            }
+            buffer[length++] = '.';
+            if (count > exponent + 1) {
+                memcpy(buffer + length, digits + exponent + 1, count - exponent - 1);
+                length += count - exponent - 1;
+            } else {
+                buffer[length++] = '0';
+            }
+        }
+        return length;
+    }
+    buffer[length++] = digits[0];
+    if (count > 1) {
+        buffer[length++] = '.';
+        memcpy(buffer + length, digits + 1, count - 1);
+        length += count - 1;
+    }
+    return length + sprintf(buffer + length, "e%d", exponent);
+}
+
+static void jet_print_int(int64_t value) {
+    char buffer[24];
+    fwrite(buffer, 1, jet_format_int(value, buffer), stdout);
+}
+
+static void jet_print_char(uint32_t value) {
+    char buffer[4];
+    fwrite(buffer, 1, jet_format_char(value, buffer), stdout);
+}
+
+static void jet_print_float(double value) {
+    char buffer[40];
+    fwrite(buffer, 1, jet_format_float(value, buffer), stdout);
+}
+
+static void jet_print_str(jet_str value) {
+    fwrite(value.data, 1, value.length, stdout);
+}
+
+/* interpolated strings are built on a stack, since an embedded expression
+   may build strings of its own; the finished text is never freed */
+typedef struct {
+    char *data;
+    size_t length;
+    size_t capacity;
+} jet_builder;
+
+static jet_builder *jet_builders;
+static size_t jet_builder_count;
+static size_t jet_builder_capacity;
+
+static void *jet_grow(void *data, size_t *capacity, size_t needed, size_t size) {
+    if (needed > *capacity) {
+        *capacity = needed > *capacity * 2 ? needed : *capacity * 2;
+        data = realloc(data, *capacity * size);
+        if (data == NULL) {
+            fputs("out of memory\n", stderr);
+            exit(101);
+        }
+    }
+    return data;
+}
+
+static void jet_begin(void) {
+    jet_builders = jet_grow(jet_builders, &jet_builder_capacity, jet_builder_count + 1, sizeof *jet_builders);
+    jet_builders[jet_builder_count++] = (jet_builder) { NULL, 0, 0 };
+}
+
+static void jet_append(const char *data, size_t length) {
+    jet_builder *builder = &jet_builders[jet_builder_count - 1];
+    builder->data = jet_grow(builder->data, &builder->capacity, builder->length + length, 1);
+    memcpy(builder->data + builder->length, data, length);
+    builder->length += length;
+}
+
+static void jet_push_int(int64_t value) {
+    char buffer[24];
+    jet_append(buffer, jet_format_int(value, buffer));
+}
+
+static void jet_push_char(uint32_t value) {
+    char buffer[4];
+    jet_append(buffer, jet_format_char(value, buffer));
+}
+
+static void jet_push_float(double value) {
+    char buffer[40];
+    jet_append(buffer, jet_format_float(value, buffer));
+}
+
+static void jet_push_str(jet_str value) {
+    jet_append(value.data, value.length);
+}
+
+static jet_str jet_end(void) {
+    jet_builder builder = jet_builders[--jet_builder_count];
+    return (jet_str) { builder.data != NULL ? builder.data : "", builder.length };
+}
+
+/* `file` indexes `jet_files`, the paths of the source files */
+static void jet_runtime_error(const char *message, int file, int line, int column) {
+    fflush(stdout);
+    fprintf(stderr, "runtime error at %s:%d:%d: %s\n", jet_files[file], line, column, message);
+    exit(101);
+}
+
+/* calls nest at most as deep as in the interpreter */
+static int jet_depth;
+
+static void jet_enter(int file, int line, int column) {
+    if (jet_depth == JET_MAX_CALL_DEPTH) {
+        jet_runtime_error("stack overflow", file, line, column);
+    }
+    jet_depth++;
+}
+
+static int64_t jet_div(int64_t lhs, int64_t rhs, int file, int line, int column) {
+    if (rhs == 0) {
+        jet_runtime_error("division by zero", file, line, column);
+    }
+    if (lhs == INT64_MIN && rhs == -1) {
+        return INT64_MIN;
+    }
+    return lhs / rhs;
+}
+
+static int64_t jet_rem(int64_t lhs, int64_t rhs, int file, int line, int column) {
+    if (rhs == 0) {
+        jet_runtime_error("division by zero", file, line, column);
+    }
+    if (rhs == -1) {
+        return 0;
+    }
+    return lhs % rhs;
+}
+
+/* shift amounts wrap around the width of `int`, as in the interpreter */
+static int64_t jet_shl(int64_t lhs, int64_t rhs) {
+    return (int64_t) ((uint64_t) lhs << (rhs & 63));
+}
+
+static int64_t jet_shr(int64_t lhs, int64_t rhs) {
+    return lhs >> (rhs & 63);
+}
+"#;
+
+fn c_type(ty: &Ty) -> String {
+    match ty {
+        Ty::Void => String::from("void"),
+        Ty::Int => String::from("int64_t"),
+        Ty::Char => String::from("uint32_t"),
+        Ty::Float => String::from("double"),
+        Ty::Str => String::from("jet_str"),
+        Ty::Pointer(ty) => format!("{}*", c_type(ty)),
+    }
+}
+
+/// Names the prelude functions that print or format a value of `ty`.
+fn runtime_suffix(ty: &Ty) -> &'static str {
+    match ty {
+        Ty::Int => "int",
+        Ty::Char => "char",
+        Ty::Float => "float",
+        Ty::Str => "str",
+        Ty::Void | Ty::Pointer(_) => unreachable!("checker rejects printing and interpolating {}", ty),
+    }
+}
+
+/// Turns a Jet identifier into a C identifier with the given prefix.
+///
+/// ASCII names are kept readable. Other names, including the ones lowering
+/// invents, get a separate prefix and an injective encoding: `_` doubles and
+/// every other non-alphanumeric character becomes `_<hex code point>_`.
+fn c_ident(prefix: &str, name: &str) -> String {
+    if name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_') {
+        return format!("{}_{}", prefix, name);
+    }
+    let mut mangled = format!("{}u_", prefix);
+    for character in name.chars() {
+        match character {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => mangled.push(character),
+            '_' => mangled.push_str("__"),
+            _ => {
+                let _ = write!(mangled, "_{:x}_", character as u32);
+            },
+        }
+    }
+    mangled
+}
+
+fn variable(name: &Symbol) -> String {
+    c_ident("v", name.as_str())
+}
+
+fn function(name: &Symbol) -> String {
+    c_ident("fn", name.as_str())
+}
+
+/// The C function holding the body of `name`; `function(name)` wraps it
+/// to count the call depth.
+fn function_body(name: &Symbol) -> String {
+    c_ident("body", name.as_str())
+}
+
+/// The arguments locating a runtime error at `location`.
+fn c_location(location: lexer::SourceLocation) -> String {
+    format!("{}, {}, {}", location.file, location.line, location.column)
+}
+
+/// Whether evaluating `node` can print, write through a pointer or fail,
+/// so that its position among its siblings matters.
+fn has_effects(node: &Node) -> bool {
+    match node {
+        Node::Call { .. } | Node::Interpolation(..) => true,
+        Node::BinaryExpr { lexpr, rexpr, op } => {
+            matches!(op.0, lexer::Operator::Div | lexer::Operator::Mod)
+                || has_effects(&lexpr.0)
+                || has_effects(&rexpr.0)
+        },
+        Node::Comparison { lexpr, rexpr, .. } => has_effects(&lexpr.0) || has_effects(&rexpr.0),
+        Node::Unary { expr, .. } => has_effects(&expr.0),
+        _ => false,
+    }
+}
+
+/// Whether `node` is a constant, which no other operand can change.
+fn is_literal(node: &Node) -> bool {
+    matches!(node, Node::Int(..) | Node::Float(..) | Node::Char(..) | Node::Str(..))
+}
+
+/// Runs `stores` before `value` through the comma operator, which C
+/// sequences left to right.
+fn sequenced(stores: Vec<String>, value: String) -> String {
+    if stores.is_empty() {
+        value
+    } else {
+        format!("({}, {})", stores.join(", "), value)
+    }
+}
+
+/// Quotes `string` as a C string literal, escaping every byte outside
+/// printable ASCII.
+fn c_literal(string: &str) -> String {
+    let mut quoted = String::from("\"");
+    for byte in string.bytes() {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => {
+                let _ = write!(quoted, "\\{:03o}", byte);
+            },
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Builds a `jet_str` for `string`, which may contain NUL bytes.
+fn c_string(string: &str) -> String {
+    format!("((jet_str) {{ {}, {} }})", c_literal(string), string.len())
+}
+
+/// Emits a C translation unit for a checked program.
+pub struct Generator {
+    checker: Checker,
+    output: String,
+    indent: usize,
+    /// The loops around the current statement, innermost last, with the
+    /// number naming their C labels.
+    loops: Vec<(Option<Symbol>, usize)>,
+    labels: usize,
+    /// C labels some `goto` jumps to, so only those are emitted.
+    targets: HashSet<String>,
+    /// Declarations of the temporaries the current C function uses to fix
+    /// the order its operands are evaluated in.
+    temporaries: Vec<String>,
+}
+
+impl Generator {
+    pub fn new(program: &CheckedProgram) -> Generator {
+        Generator {
+            checker: Checker::new(program),
+            output: String::new(),
+            indent: 0,
+            loops: Vec::new(),
+            labels: 0,
+            targets: HashSet::new(),
+            temporaries: Vec::new(),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.output.push_str("    ");
+        }
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    /// Declares a fresh temporary of type `ty` in the current C function.
+    fn temporary(&mut self, ty: &Ty) -> String {
+        let name = format!("jet_t{}", self.temporaries.len());
+        self.temporaries.push(format!("{} {};", c_type(ty), name));
+        name
+    }
+
+    /// Writes the temporaries declared since the function body began at
+    /// byte `start` of the output.
+    fn declare_temporaries(&mut self, start: usize) {
+        let mut declarations = String::new();
+        for declaration in self.temporaries.drain(..) {
+            for _ in 0..self.indent {
+                declarations.push_str("    ");
+            }
+            declarations.push_str(&declaration);
+            declarations.push('\n');
+        }
+        self.output.insert_str(start, &declarations);
+    }
+
+    /// Translates the operands of a call or an operator. C evaluates them in
+    /// no particular order, so when one of them can have side effects all
+    /// but the last are first stored in temporaries, left to right like the
+    /// interpreter. Returns the stores to run first and the operand values.
+    fn operands(&mut self, nodes: &[&Node]) -> (Vec<String>, Vec<String>) {
+        let ordered = nodes.iter().any(|node| has_effects(node));
+        let mut stores = Vec::new();
+        let mut values = Vec::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let value = self.expr(node);
+            let ty = self.checker.expr(node);
+            if ordered && index + 1 < nodes.len() && !is_literal(node) && ty != Ty::Void {
+                let temporary = self.temporary(&ty);
+                stores.push(format!("{} = {}", temporary, value));
+                values.push(temporary);
+            } else {
+                values.push(value);
+            }
+        }
+        (stores, values)
+    }
+
+    fn expr(&mut self, node: &Node) -> String {
+        match node {
+            Node::Int(int, _) => format!("INT64_C({})", int),
+            Node::Str(string, _) => c_string(string.as_str()),
+            Node::Interpolation(parts, _) => {
+                // the comma operator keeps the parts in source order
+                let mut steps = vec![String::from("jet_begin()")];
+                for part in parts {
+                    let ty = self.checker.expr(part);
+                    steps.push(format!("jet_push_{}({})", runtime_suffix(&ty), self.expr(part)));
+                }
+                steps.push(String::from("jet_end()"));
+                format!("({})", steps.join(", "))
+            },
+            // Debug formatting always yields a valid C double literal
+            Node::Float(float, _) => format!("{:?}", float),
+            Node::Char(character, _) => format!("UINT32_C({})", *character as u32),
+            Node::Ident(name, _) => variable(name),
+            Node::BinaryExpr { lexpr, rexpr, op } => {
+                let float = self.checker.expr(&lexpr.0) == Ty::Float;
+                let (stores, values) = self.operands(&[&lexpr.0, &rexpr.0]);
+                let (left, right) = (&values[0], &values[1]);
+                if float {
+                    return sequenced(stores, format!("({} {} {})", left, op.0, right));
+                }
+                let value = match op.0 {
+                    lexer::Operator::Add => format!("(int64_t) ((uint64_t) {} + (uint64_t) {})", left, right),
+                    lexer::Operator::Sub => format!("(int64_t) ((uint64_t) {} - (uint64_t) {})", left, right),
+                    lexer::Operator::Mul => format!("(int64_t) ((uint64_t) {} * (uint64_t) {})", left, right),
+                    lexer::Operator::Div => format!("jet_div({}, {}, {})", left, right, c_location(op.1)),
+                    lexer::Operator::Mod => format!("jet_rem({}, {}, {})", left, right, c_location(op.1)),
+                    lexer::Operator::Shl => format!("jet_shl({}, {})", left, right),
+                    lexer::Operator::Shr => format!("jet_shr({}, {})", left, right),
+                    lexer::Operator::And | lexer::Operator::Or | lexer::Operator::Xor => {
+                        format!("({} {} {})", left, op.0, right)
+                    },
+                    lexer::Operator::LogicalAnd | lexer::Operator::LogicalOr => {
+                        format!("(int64_t) ({} {} {})", left, op.0, right)
+                    },
+                    lexer::Operator::Not | lexer::Operator::BitNot => unreachable!("parser only emits {} as a prefix", op.0),
+                };
+                sequenced(stores, value)
+            },
+            Node::Unary { op, expr } => {
+                let float = self.checker.expr(&expr.0) == Ty::Float;
+                let value = self.expr(&expr.0);
+                match op.0 {
+                    lexer::Operator::Sub if float => format!("(-{})", value),
+                    lexer::Operator::Sub => format!("(int64_t) (0 - (uint64_t) {})", value),
+                    lexer::Operator::Not => format!("((int64_t) !{})", value),
+                    lexer::Operator::BitNot => format!("(~{})", value),
+                    lexer::Operator::And => format!("(&{})", value),
+                    lexer::Operator::Mul => format!("(*{})", value),
+                    _ => unreachable!("parser never emits {} as a prefix", op.0),
+                }
+            },
+            Node::Comparison { lexpr, rexpr, op } => {
+                let (stores, values) = self.operands(&[&lexpr.0, &rexpr.0]);
+                let (left, right) = (&values[0], &values[1]);
+                let op = match op.0 {
+                    lexer::Comparison::Equal => "==",
+                    lexer::Comparison::NotEqual => "!=",
+                    lexer::Comparison::Bigger => ">",
+                    lexer::Comparison::Smaller => "<",
+                    lexer::Comparison::BiggerEqual => ">=",
+                    lexer::Comparison::SmallerEqual => "<=",
+                };
+                sequenced(stores, format!("(int64_t) ({} {} {})", left, op, right))
+            },
+            Node::Call { name, parameters } => {
+                if name.0.as_str() == "print" {
+                    let ty = self.checker.expr(&parameters.0[0]);
+                    return format!("jet_print_{}({})", runtime_suffix(&ty), self.expr(&parameters.0[0]));
+                }
+                let (stores, arguments) = self.operands(&parameters.0.iter().collect::<Vec<&Node>>());
+                let mut call = vec![c_location(name.1)];
+                call.extend(arguments);
+                sequenced(stores, format!("{}({})", function(&name.0), call.join(", ")))
+            },
+            _ => unreachable!("checker let through a non-expression {:?}", node),
+        }
+    }
+
+    fn block(&mut self, body: &[Node]) {
+        self.checker.push_scope();
+        for node in body {
+            self.statement(node);
+        }
+        self.checker.pop_scope();
+    }
+
+    /// Emits `break` or `continue`. Jumps out of an enclosing loop become a
+    /// `goto` past the end of that loop or of its body.
+    fn jump(&mut self, keyword: &str, label: &Option<(Symbol, lexer::SourceLocation)>) {
+        let innermost = &self.loops.last().expect("checker keeps jumps inside loops").0;
+        match label {
+            Some(label) if innermost.as_ref() != Some(&label.0) => {
+                let id = self.loops.iter().rev()
+                    .find(|(name, _)| name.as_ref() == Some(&label.0))
+                    .expect("label resolved by the checker")
+                    .1;
+                let target = format!("jet_{}_{}", keyword, id);
+                self.line(&format!("goto {};", target));
+                self.targets.insert(target);
+            },
+            _ => self.line(&format!("{};", keyword)),
+        }
+    }
+
+    /// Emits the C label `jet_<keyword>_<id>` if anything jumps to it.
+    fn target(&mut self, keyword: &str, id: usize) {
+        let target = format!("jet_{}_{}", keyword, id);
+        if self.targets.contains(&target) {
+            self.line(&format!("{}: ;", target));
+        }
+    }
+
+    fn statement(&mut self, node: &Node) {
+        match node {
+            Node::Nop | Node::Function { .. } => {},
+            Node::Variable { name, var_type, expr, .. } => {
+                let ty = Ty::from(&var_type.0);
+                let mut value = self.expr(&expr.0);
+                if self.checker.lookup(&name.0).is_some() {
+                    // a C variable is in scope in its own initializer, where
+                    // the shadowed variable must still be the one read
+                    let temporary = self.temporary(&ty);
+                    self.line(&format!("{} = {};", temporary, value));
+                    value = temporary;
+                }
+                self.line(&format!("{} {} = {};", c_type(&ty), variable(&name.0), value));
+                self.checker.declare(name.0.clone(), ty, name.1);
+            },
+            Node::Return { expr } => {
+                let value = self.expr(&expr.0);
+                self.line(&format!("return {};", value));
+            },
+            Node::If { test, then_body, else_body } => {
+                let test = self.expr(&test.0);
+                self.line(&format!("if ({}) {{", test));
+                self.indent += 1;
+                self.block(&then_body.0);
+                self.indent -= 1;
+                if !else_body.0.is_empty() {
+                    self.line("} else {");
+                    self.indent += 1;
+                    self.block(&else_body.0);
+                    self.indent -= 1;
+                }
+                self.line("}");
+            },
+            Node::While { label, test, body } => {
+                let test = self.expr(&test.0);
+                let id = self.labels;
+                self.labels += 1;
+                self.loops.push((label.as_ref().map(|label| label.0.clone()), id));
+                self.line(&format!("while ({}) {{", test));
+                self.indent += 1;
+                self.block(&body.0);
+                self.target("continue", id);
+                self.indent -= 1;
+                self.line("}");
+                self.target("break", id);
+                self.loops.pop();
+            },
+            Node::Break { label, .. } => self.jump("break", label),
+            Node::Continue { label, .. } => self.jump("continue", label),
+            Node::Block { body } => {
+                self.line("{");
+                self.indent += 1;
+                self.block(&body.0);
+                self.indent -= 1;
+                self.line("}");
+            },
+            Node::Assign { target, expr } => {
+                let value = self.expr(&expr.0);
+                let place = self.expr(&target.0);
+                if let Node::Ident(..) = *target.0 {
+                    self.line(&format!("{} = {};", place, value));
+                } else {
+                    // C leaves the order of the two sides open; compute the value first
+                    let ty = self.checker.expr(&expr.0);
+                    self.line("{");
+                    self.indent += 1;
+                    self.line(&format!("{} jet_value = {};", c_type(&ty), value));
+                    self.line(&format!("{} = jet_value;", place));
+                    self.indent -= 1;
+                    self.line("}");
+                }
+            },
+            _ => {
+                let value = self.expr(node);
+                self.line(&format!("{};", value));
+            },
+        }
+    }
+
+    /// Returns the C signatures of `name`: the wrapper that callers pass
+    /// their location to, and the function holding the body.
+    fn signatures(&self, name: &Symbol) -> (String, String) {
+        let signature = self.checker.signature(name).expect("function collected by the checker");
+        let parameters = signature.parameters.iter()
+            .enumerate()
+            .map(|(index, ty)| format!("{} p_{}", c_type(ty), index))
+            .collect::<Vec<String>>();
+        let mut located = vec![String::from("int file, int line, int column")];
+        located.extend(parameters.iter().cloned());
+        let parameters = if parameters.is_empty() {
+            String::from("void")
+        } else {
+            parameters.join(", ")
+        };
+        let return_type = c_type(&signature.return_type);
+        (
+            format!("{} {}({})", return_type, function(name), located.join(", ")),
+            format!("{} {}({})", return_type, function_body(name), parameters),
+        )
+    }
+
+    fn function(&mut self, node: &Node) {
+        if let Node::Function { name, parameters, return_type, body, .. } = node {
+            let (wrapper, header) = self.signatures(&name.0);
+            self.line(&format!("static {} {{", header));
+            self.indent += 1;
+            let start = self.output.len();
+            let frame = self.checker.enter_function(&parameters.0, &return_type.0);
+            // parameters are copied into locals so the body can shadow them like any other scope
+            for (index, (parameter, typeid, _)) in parameters.0.iter().enumerate() {
+                self.line(&format!("{} {} = p_{};", c_type(&Ty::from(typeid)), variable(parameter), index));
+            }
+            self.line("{");
+            self.indent += 1;
+            self.block(&body.0);
+            self.indent -= 1;
+            self.line("}");
+            self.checker.leave_function(frame);
+            self.declare_temporaries(start);
+            self.indent -= 1;
+            self.line("}");
+            self.line("");
+
+            let arguments = (0..parameters.0.len())
+                .map(|index| format!("p_{}", index))
+                .collect::<Vec<String>>()
+                .join(", ");
+            let call = format!("{}({})", function_body(&name.0), arguments);
+            self.line(&format!("{} {{", wrapper));
+            self.indent += 1;
+            self.line("jet_enter(file, line, column);");
+            if Ty::from(&return_type.0) == Ty::Void {
+                self.line(&format!("{};", call));
+                self.line("jet_depth--;");
+            } else {
+                self.line(&format!("{} result = {};", c_type(&Ty::from(&return_type.0)), call));
+                self.line("jet_depth--;");
+                self.line("return result;");
+            }
+            self.indent -= 1;
+            self.line("}");
+            self.line("");
+        }
+    }
+
+    /// `sources` holds the files `program` was parsed from, whose paths
+    /// runtime errors report.
+    pub fn generate(mut self, program: &CheckedProgram, sources: &SourceMap) -> String {
+        self.line(&format!("#define JET_MAX_CALL_DEPTH {}", MAX_CALL_DEPTH));
+        let files = sources.paths()
+            .map(|path| c_literal(&path.display().to_string()))
+            .collect::<Vec<String>>();
+        self.line(&format!("static const char *const jet_files[] = {{ {} }};", files.join(", ")));
+        self.output.push_str(PRELUDE);
+        self.line("");
+        for node in &program.body {
+            if let Node::Function { name, .. } = node {
+                let (wrapper, _) = self.signatures(&name.0);
+                self.line(&format!("{};", wrapper));
+            }
+        }
+        self.line("");
+        for node in &program.body {
+            self.function(node);
+        }
+
+        self.line("int main(void) {");
+        self.indent += 1;
+        let start = self.output.len();
+        for node in &program.body {
+            self.statement(node);
+        }
+        self.line("return 0;");
+        self.declare_temporaries(start);
+        self.indent -= 1;
+        self.line("}");
+        self.output
+    }
+}
+
+/// Translates a checked program parsed from `sources` into a C
+/// translation unit.
+pub fn generate(program: &CheckedProgram, sources: &SourceMap) -> String {
+    Generator::new(program).generate(program, sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::{self, CheckedProgram, SourceMap};
+    use crate::interp;
+
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Output};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A path in the temporary directory no other test uses.
+    fn temp_path(extension: &str) -> PathBuf {
+        static PATHS: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("jet-test-{}-{}{}", std::process::id(), PATHS.fetch_add(1, Ordering::Relaxed), extension);
+        env::temp_dir().join(name)
+    }
+
+    /// Builds `program` with the C compiler in $CC and runs it.
+    fn run_native(program: &CheckedProgram, sources: &SourceMap) -> Output {
+        let c_file = temp_path(".c");
+        let executable = temp_path("");
+        fs::write(&c_file, super::generate(program, sources)).expect("C source is written");
+        let compiler = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+        let status = Command::new(&compiler)
+            .arg("-o")
+            .arg(&executable)
+            .arg(&c_file)
+            .status()
+            .expect("C compiler runs");
+        let _ = fs::remove_file(&c_file);
+        assert!(status.success(), "C compiler failed");
+        let output = Command::new(&executable).output().expect("executable runs");
+        let _ = fs::remove_file(&executable);
+        output
+    }
+
+    /// Runs `source` in the interpreter and as a native executable, asserts
+    /// that both print the same and returns what they printed.
+    fn run_both(source: &str) -> String {
+        let mut sources = SourceMap::new();
+        let file = sources.add(PathBuf::from("<input>"), source.to_string());
+        let program = driver::compile_file(&mut sources, file).expect("test program compiles");
+        let mut interpreted = Vec::new();
+        interp::run(&program, &mut interpreted).expect("test program runs");
+
+        let native = run_native(&program, &sources);
+        let interpreted = String::from_utf8(interpreted).expect("output is UTF-8");
+        assert_eq!(String::from_utf8_lossy(&native.stdout), interpreted, "on:\n{}", source);
+        interpreted
+    }
+
+    #[test]
+    fn shadowing_initializer_reads_the_outer_variable() {
+        let source = "\
+fn inc(int x): int {
+    let x: int = x + 1
+    return x
+}
+print(inc(9))
+print(\" \")
+let y: int = 21
+if 1 {
+    let y: int = y * 2
+    print(y)
+}
+";
+        assert_eq!(run_both(source), "10 42");
+    }
+
+    #[test]
+    fn for_body_shadows_the_loop_variable() {
+        let source = "\
+for i in 0..5 {
+    if i == 2 {
+        continue
+    }
+    let i: int = i * 10
+    print(i)
+    print(\" \")
+}
+";
+        assert_eq!(run_both(source), "0 10 30 40 ");
+    }
+
+    #[test]
+    fn runtime_errors_name_the_file() {
+        let library = temp_path(".jet");
+        fs::write(&library, "fn divide(int left, int right): int {\n    return left / right\n}\n")
+            .expect("library is written");
+        let name = library.file_name().and_then(|name| name.to_str()).expect("temporary name is UTF-8");
+        let main = library.with_extension("main.jet");
+        let source = format!("#include \"{}\"\nprint(divide(6, 3))\nprint(divide(1, 0))\n", name);
+
+        let mut sources = SourceMap::new();
+        let file = sources.add(main, source);
+        let program = driver::compile_file(&mut sources, file).expect("test program compiles");
+        let _ = fs::remove_file(&library);
+        let mut interpreted = Vec::new();
+        let error = interp::run(&program, &mut interpreted).expect_err("division by zero fails");
+        assert_eq!(sources.path(error.location.file), Path::new(&library));
+
+        let native = run_native(&program, &sources);
+        assert_eq!(native.status.code(), Some(101));
+        assert_eq!(String::from_utf8_lossy(&native.stdout), "2");
+        assert_eq!(
+            String::from_utf8_lossy(&native.stderr),
+            format!(
+                "runtime error at {}:{}:{}: division by zero\n",
+                library.display(), error.location.line, error.location.column,
+            ),
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use crate::lexer;
+
+use std::fmt;
+
+
+/// An error found while compiling a program, located in its source.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: lexer::SourceLocation,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, location: lexer::SourceLocation) -> Diagnostic {
+        Diagnostic { message, location }
+    }
+}
+
+impl From<lexer::LexError> for Diagnostic {
+    fn from(error: lexer::LexError) -> Diagnostic {
+        Diagnostic::new(error.to_string(), error.span.start)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.location.line, self.location.column, self.message)
+    }
+}
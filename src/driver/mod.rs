@@ -0,0 +1,235 @@
+use crate::lexer;
+use crate::ast;
+use crate::check;
+use crate::lower;
+use crate::diagnostic::Diagnostic;
+use lexer::{Keyword, SpannedToken, Token};
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+
+/// The result of parsing a whole source file.
+#[derive(Debug)]
+pub struct Program {
+    pub body: Vec<ast::Node>,
+    /// Native libraries requested with `#link`, in order of appearance.
+    pub links: Vec<String>,
+}
+
+/// A program that passed the checker and was lowered, the only form the
+/// backends accept. Just `compile` and `compile_file` create one.
+#[derive(Debug)]
+pub struct CheckedProgram(Program);
+
+impl Deref for CheckedProgram {
+    type Target = Program;
+
+    fn deref(&self) -> &Program {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+/// Every file taking part in a compilation; `SourceLocation::file`
+/// indexes into it.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn add(&mut self, path: PathBuf, source: String) -> usize {
+        self.files.push(SourceFile { path, source });
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, file: usize) -> &SourceFile {
+        &self.files[file]
+    }
+
+    pub fn path(&self, file: usize) -> &Path {
+        &self.files[file].path
+    }
+
+    /// The paths of all files, in the order of their ids.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|file| file.path.as_path())
+    }
+}
+
+pub fn read_source(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+}
+
+/// Lexes a file and splices `#include`d files into its token stream,
+/// collecting `#link` directives on the way.
+struct Expander<'a> {
+    sources: &'a mut SourceMap,
+    /// Files currently being expanded, to detect include cycles.
+    stack: Vec<PathBuf>,
+    /// Files already spliced in; including one again is a no-op.
+    included: HashSet<PathBuf>,
+    links: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Expander<'a> {
+    fn new(sources: &'a mut SourceMap) -> Expander<'a> {
+        Expander {
+            sources,
+            stack: Vec::new(),
+            included: HashSet::new(),
+            links: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String, location: lexer::SourceLocation) {
+        self.diagnostics.push(Diagnostic::new(message, location));
+    }
+
+    fn expand(&mut self, file: usize) -> Vec<SpannedToken> {
+        let path = self.sources.path(file);
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.included.insert(key.clone());
+        self.stack.push(key);
+
+        let mut tokens = Vec::new();
+        for result in lexer::Lexer::with_file(&self.sources.file(file).source, file) {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(error) => self.diagnostics.push(Diagnostic::from(error)),
+            }
+        }
+
+        let mut expanded = Vec::with_capacity(tokens.len());
+        let mut depth = 0;
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            let directive = match token.token {
+                Token::Keyword(Keyword::Include) => "#include",
+                Token::Keyword(Keyword::Link) => "#link",
+                Token::OpenBrace | Token::CloseBrace => {
+                    depth += if token.token == Token::OpenBrace { 1 } else { -1 };
+                    expanded.push(token);
+                    continue;
+                },
+                _ => {
+                    expanded.push(token);
+                    continue;
+                },
+            };
+            let location = token.span.start;
+            if depth != 0 {
+                self.error(format!("`{}` is only allowed at the top level", directive), location);
+            }
+            let argument = match tokens.next_if(|next| matches!(next.token, Token::Str(_))) {
+                Some(SpannedToken { token: Token::Str(argument), .. }) => argument,
+                _ => {
+                    self.error(format!("expected a string after `{}`", directive), location);
+                    continue;
+                },
+            };
+            if let Some(next) = tokens.peek().filter(|next| next.token != Token::Newline) {
+                let location = next.span.start;
+                self.error(format!("expected the end of the line after `{}`", directive), location);
+            }
+
+            if directive == "#link" {
+                if !self.links.iter().any(|link| link == argument.as_str()) {
+                    self.links.push(argument.to_string());
+                }
+            } else {
+                expanded.extend(self.include(file, argument.as_str(), location));
+            }
+        }
+
+        self.stack.pop();
+        expanded
+    }
+
+    fn include(&mut self, from: usize, argument: &str, location: lexer::SourceLocation) -> Vec<SpannedToken> {
+        let path = match self.sources.path(from).parent() {
+            Some(directory) => directory.join(argument),
+            None => PathBuf::from(argument),
+        };
+        let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(start) = self.stack.iter().position(|file| *file == key) {
+            let cycle = self.stack[start..].iter()
+                .chain([&key])
+                .map(|file| file.display().to_string())
+                .collect::<Vec<String>>()
+                .join(" -> ");
+            self.error(format!("include cycle: {}", cycle), location);
+            return Vec::new();
+        }
+        if self.included.contains(&key) {
+            return Vec::new();
+        }
+        match read_source(&path) {
+            Ok(source) => {
+                let file = self.sources.add(path, source);
+                self.expand(file)
+            },
+            Err(err) => {
+                self.error(format!("failed to read '{}': {}", path.display(), err), location);
+                Vec::new()
+            },
+        }
+    }
+}
+
+/// Tokenizes `file` and everything it includes, then builds a single
+/// program from the combined token stream.
+pub fn parse_file(sources: &mut SourceMap, file: usize) -> Result<Program, Vec<Diagnostic>> {
+    let mut expander = Expander::new(sources);
+    let tokens = expander.expand(file);
+    if !expander.diagnostics.is_empty() {
+        return Err(expander.diagnostics);
+    }
+    let links = expander.links;
+    let tokens = tokens.into_iter()
+        .map(|spanned| (spanned.token, spanned.span.start))
+        .collect::<Vec<_>>();
+    let body = ast::build_ast(&tokens).map_err(|diagnostic| vec![diagnostic])?;
+    Ok(Program { body, links })
+}
+
+/// Parses, checks and lowers `file`, returning a program every backend accepts.
+pub fn compile_file(sources: &mut SourceMap, file: usize) -> Result<CheckedProgram, Vec<Diagnostic>> {
+    let mut program = parse_file(sources, file)?;
+    let diagnostics = check::check(&program);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    program.body = lower::lower(program.body);
+    Ok(CheckedProgram(program))
+}
+
+/// Parses an in-memory source; its `#include`s are resolved against the
+/// current directory.
+pub fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let mut sources = SourceMap::new();
+    let file = sources.add(PathBuf::from("<input>"), source.to_string());
+    parse_file(&mut sources, file)
+}
+
+/// Parses, checks and lowers an in-memory source.
+pub fn compile(source: &str) -> Result<CheckedProgram, Vec<Diagnostic>> {
+    let mut sources = SourceMap::new();
+    let file = sources.add(PathBuf::from("<input>"), source.to_string());
+    compile_file(&mut sources, file)
+}
@@ -0,0 +1,389 @@
+use crate::lexer;
+use crate::ast::Node;
+use crate::driver::CheckedProgram;
+use crate::symbol::Symbol;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+
+/// Deepest call nesting before the interpreter gives up.
+pub(crate) const MAX_CALL_DEPTH: usize = 1024;
+/// Native stack reserved for each level of call nesting. A call recurses
+/// through `block`, `statement` and `eval`, about 16 KiB per level in an
+/// unoptimized build; the rest covers statements and expressions nested
+/// inside the function.
+const STACK_PER_CALL: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Void,
+    Int(i64),
+    Char(char),
+    Float(f64),
+    Str(String),
+    /// The slot holding the variable pointed to, and the id of that
+    /// variable so that a pointer outliving it is caught.
+    Pointer(usize, u64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Void => Ok(()),
+            Value::Int(int) => write!(f, "{}", int),
+            Value::Char(character) => write!(f, "{}", character),
+            // `1.0`, `0.25` and `1e16` alike; the C backend prints the same digits
+            Value::Float(float) => write!(f, "{:?}", float),
+            Value::Str(string) => write!(f, "{}", string),
+            Value::Pointer(slot, _) => write!(f, "<pointer to slot {}>", slot),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    pub location: lexer::SourceLocation,
+}
+
+enum Flow {
+    Next,
+    Return(Value),
+    /// Leaves the innermost loop, or the one with the given label.
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+}
+
+/// Tree-walking interpreter over a checked program.
+///
+/// Variables live in `slots`, which grows and shrinks like a call stack;
+/// scopes map names to slot indices so that pointers can refer to them.
+/// Every variable gets a fresh id, so a slot reused after its variable
+/// went out of scope is not mistaken for the old one.
+pub struct Interpreter<'a, W: Write> {
+    functions: HashMap<Symbol, &'a Node>,
+    slots: Vec<(u64, Value)>,
+    next_id: u64,
+    scopes: Vec<HashMap<Symbol, usize>>,
+    depth: usize,
+    out: W,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    pub fn new(program: &'a CheckedProgram, out: W) -> Interpreter<'a, W> {
+        let mut functions = HashMap::new();
+        for node in &program.body {
+            if let Node::Function { name, .. } = node {
+                functions.insert(name.0.clone(), node);
+            }
+        }
+        Interpreter {
+            functions,
+            slots: Vec::new(),
+            next_id: 0,
+            scopes: vec![HashMap::new()],
+            depth: 0,
+            out,
+        }
+    }
+
+    fn lookup(&self, name: &Symbol) -> usize {
+        *self.scopes.iter().rev()
+            .find_map(|scope| scope.get(name))
+            .expect("variable resolved by the checker")
+    }
+
+    fn declare(&mut self, name: Symbol, value: Value) {
+        self.slots.push((self.next_id, value));
+        self.next_id += 1;
+        self.scopes.last_mut().unwrap().insert(name, self.slots.len() - 1);
+    }
+
+    /// Resolves an expression checked by `is_place` to the slot it names.
+    fn place(&mut self, node: &Node) -> Result<usize, RuntimeError> {
+        match node {
+            Node::Ident(name, _) => Ok(self.lookup(name)),
+            Node::Unary { op, expr } => self.pointer(&expr.0, op.1),
+            _ => unreachable!("checker only allows taking the address of a place"),
+        }
+    }
+
+    /// Evaluates a pointer, failing if the variable it points to has gone out of scope.
+    fn pointer(&mut self, node: &Node, location: lexer::SourceLocation) -> Result<usize, RuntimeError> {
+        match self.expr(node)? {
+            Value::Pointer(slot, id) if self.slots.get(slot).is_some_and(|(live, _)| *live == id) => Ok(slot),
+            Value::Pointer(..) => Err(RuntimeError {
+                message: String::from("dereference of a dangling pointer"),
+                location,
+            }),
+            value => unreachable!("checker let through a non-pointer {:?}", value),
+        }
+    }
+
+    fn int(&mut self, node: &Node) -> Result<i64, RuntimeError> {
+        match self.expr(node)? {
+            Value::Int(int) => Ok(int),
+            value => unreachable!("checker let through a non-integer {:?}", value),
+        }
+    }
+
+    fn float(&mut self, node: &Node) -> Result<f64, RuntimeError> {
+        match self.expr(node)? {
+            Value::Float(float) => Ok(float),
+            value => unreachable!("checker let through a non-float {:?}", value),
+        }
+    }
+
+    fn expr(&mut self, node: &Node) -> Result<Value, RuntimeError> {
+        match node {
+            Node::Int(int, _) => Ok(Value::Int(*int as i64)),
+            Node::Float(float, _) => Ok(Value::Float(*float)),
+            Node::Str(string, _) => Ok(Value::Str(string.to_string())),
+            Node::Interpolation(parts, _) => {
+                let mut string = String::new();
+                for part in parts {
+                    // parts read exactly as `print` would show them
+                    string.push_str(&self.expr(part)?.to_string());
+                }
+                Ok(Value::Str(string))
+            },
+            Node::Char(character, _) => Ok(Value::Char(*character)),
+            Node::Ident(name, _) => Ok(self.slots[self.lookup(name)].1.clone()),
+            Node::BinaryExpr { lexpr, rexpr, op } => {
+                let left = match self.expr(&lexpr.0)? {
+                    Value::Int(int) => int,
+                    Value::Float(left) => {
+                        let right = self.float(&rexpr.0)?;
+                        let value = match op.0 {
+                            lexer::Operator::Add => left + right,
+                            lexer::Operator::Sub => left - right,
+                            lexer::Operator::Mul => left * right,
+                            lexer::Operator::Div => left / right,
+                            _ => unreachable!("checker only allows arithmetic on floats"),
+                        };
+                        return Ok(Value::Float(value));
+                    },
+                    value => unreachable!("checker let through {:?}", value),
+                };
+                // the logical operators only evaluate their right side when needed
+                match op.0 {
+                    lexer::Operator::LogicalAnd if left == 0 => return Ok(Value::Int(0)),
+                    lexer::Operator::LogicalOr if left != 0 => return Ok(Value::Int(1)),
+                    _ => {},
+                }
+                let right = self.int(&rexpr.0)?;
+                let value = match op.0 {
+                    lexer::Operator::Add => left.wrapping_add(right),
+                    lexer::Operator::Sub => left.wrapping_sub(right),
+                    lexer::Operator::Mul => left.wrapping_mul(right),
+                    lexer::Operator::Div | lexer::Operator::Mod if right == 0 => {
+                        return Err(RuntimeError {
+                            message: String::from("division by zero"),
+                            location: op.1,
+                        });
+                    },
+                    lexer::Operator::Div => left.wrapping_div(right),
+                    lexer::Operator::Mod => left.wrapping_rem(right),
+                    lexer::Operator::And => left & right,
+                    lexer::Operator::Or => left | right,
+                    lexer::Operator::Xor => left ^ right,
+                    // shift amounts wrap around the width of `int`
+                    lexer::Operator::Shl => left.wrapping_shl(right as u32),
+                    lexer::Operator::Shr => left.wrapping_shr(right as u32),
+                    lexer::Operator::LogicalAnd | lexer::Operator::LogicalOr => (right != 0) as i64,
+                    lexer::Operator::Not | lexer::Operator::BitNot => unreachable!("parser only emits {} as a prefix", op.0),
+                };
+                Ok(Value::Int(value))
+            },
+            Node::Unary { op, expr } => {
+                let value = match op.0 {
+                    lexer::Operator::And => {
+                        let slot = self.place(&expr.0)?;
+                        return Ok(Value::Pointer(slot, self.slots[slot].0));
+                    },
+                    lexer::Operator::Mul => {
+                        let slot = self.pointer(&expr.0, op.1)?;
+                        return Ok(self.slots[slot].1.clone());
+                    },
+                    _ => self.expr(&expr.0)?,
+                };
+                let value = match (op.0, value) {
+                    (lexer::Operator::Sub, Value::Float(float)) => return Ok(Value::Float(-float)),
+                    (_, Value::Int(int)) => int,
+                    (_, value) => unreachable!("checker let through {:?}", value),
+                };
+                let value = match op.0 {
+                    lexer::Operator::Sub => value.wrapping_neg(),
+                    lexer::Operator::Not => (value == 0) as i64,
+                    lexer::Operator::BitNot => !value,
+                    _ => unreachable!("parser never emits {} as a prefix", op.0),
+                };
+                Ok(Value::Int(value))
+            },
+            Node::Comparison { lexpr, rexpr, op } => {
+                let left = self.expr(&lexpr.0)?;
+                let right = self.expr(&rexpr.0)?;
+                // NaN is unordered: only `!=` holds for it
+                let ordering = match (&left, &right) {
+                    (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+                    (Value::Char(left), Value::Char(right)) => Some(left.cmp(right)),
+                    (Value::Float(left), Value::Float(right)) => left.partial_cmp(right),
+                    _ => unreachable!("checker let through {:?} and {:?}", left, right),
+                };
+                let result = match op.0 {
+                    lexer::Comparison::Equal => ordering.is_some_and(Ordering::is_eq),
+                    lexer::Comparison::NotEqual => !ordering.is_some_and(Ordering::is_eq),
+                    lexer::Comparison::Bigger => ordering.is_some_and(Ordering::is_gt),
+                    lexer::Comparison::Smaller => ordering.is_some_and(Ordering::is_lt),
+                    lexer::Comparison::BiggerEqual => ordering.is_some_and(Ordering::is_ge),
+                    lexer::Comparison::SmallerEqual => ordering.is_some_and(Ordering::is_le),
+                };
+                Ok(Value::Int(result as i64))
+            },
+            Node::Call { name, parameters } => {
+                let mut arguments = Vec::with_capacity(parameters.0.len());
+                for parameter in &parameters.0 {
+                    arguments.push(self.expr(parameter)?);
+                }
+                self.call(name, arguments)
+            },
+            _ => unreachable!("checker let through a non-expression {:?}", node),
+        }
+    }
+
+    fn call(&mut self, name: &(Symbol, lexer::SourceLocation), arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        if name.0.as_str() == "print" {
+            if write!(self.out, "{}", arguments[0]).is_err() {
+                return Err(RuntimeError { message: String::from("failed to write output"), location: name.1 });
+            }
+            return Ok(Value::Void);
+        }
+        let function = self.functions[&name.0];
+        let (parameters, body) = match function {
+            Node::Function { parameters, body, .. } => (&parameters.0, &body.0),
+            _ => unreachable!(),
+        };
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(RuntimeError { message: String::from("stack overflow"), location: name.1 });
+        }
+
+        let base = self.slots.len();
+        let caller = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+        for ((parameter, _, _), argument) in parameters.iter().zip(arguments) {
+            self.declare(parameter.clone(), argument);
+        }
+        self.depth += 1;
+        let flow = self.block(body);
+        self.depth -= 1;
+        self.scopes = caller;
+        self.slots.truncate(base);
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            Flow::Next => Ok(Value::Void),
+            Flow::Break(_) | Flow::Continue(_) => unreachable!("checker keeps `break` and `continue` inside loops"),
+        }
+    }
+
+    fn block(&mut self, body: &[Node]) -> Result<Flow, RuntimeError> {
+        let base = self.slots.len();
+        self.scopes.push(HashMap::new());
+        let mut flow = Ok(Flow::Next);
+        for node in body {
+            flow = self.statement(node);
+            if !matches!(flow, Ok(Flow::Next)) {
+                break;
+            }
+        }
+        self.scopes.pop();
+        self.slots.truncate(base);
+        flow
+    }
+
+    fn statement(&mut self, node: &Node) -> Result<Flow, RuntimeError> {
+        match node {
+            Node::Nop | Node::Function { .. } => {},
+            Node::Variable { name, expr, .. } => {
+                let value = self.expr(&expr.0)?;
+                self.declare(name.0.clone(), value);
+            },
+            Node::Return { expr } => return Ok(Flow::Return(self.expr(&expr.0)?)),
+            Node::If { test, then_body, else_body } => {
+                return if self.int(&test.0)? != 0 {
+                    self.block(&then_body.0)
+                } else {
+                    self.block(&else_body.0)
+                };
+            },
+            Node::While { label, test, body } => {
+                let label = label.as_ref().map(|label| &label.0);
+                while self.int(&test.0)? != 0 {
+                    match self.block(&body.0)? {
+                        Flow::Next => {},
+                        Flow::Break(target) if target.is_none() || target.as_ref() == label => break,
+                        Flow::Continue(target) if target.is_none() || target.as_ref() == label => continue,
+                        // a return, or a jump out of an enclosing loop
+                        flow => return Ok(flow),
+                    }
+                }
+            },
+            Node::Break { label, .. } => return Ok(Flow::Break(label.as_ref().map(|label| label.0.clone()))),
+            Node::Continue { label, .. } => return Ok(Flow::Continue(label.as_ref().map(|label| label.0.clone()))),
+            Node::Block { body } => return self.block(&body.0),
+            Node::Assign { target, expr } => {
+                // the value is computed before the place, as in the C backend
+                let value = self.expr(&expr.0)?;
+                let slot = self.place(&target.0)?;
+                self.slots[slot].1 = value;
+            },
+            _ => {
+                self.expr(node)?;
+            },
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Executes the top level and returns the exit code of the program.
+    pub fn run(&mut self, program: &'a CheckedProgram) -> Result<i64, RuntimeError> {
+        let result = self.top_level(program);
+        // whatever was printed before a runtime error still belongs to the output
+        if self.out.flush().is_err() {
+            return Err(RuntimeError {
+                message: String::from("failed to write output"),
+                location: lexer::SourceLocation::default(),
+            });
+        }
+        result
+    }
+
+    fn top_level(&mut self, program: &'a CheckedProgram) -> Result<i64, RuntimeError> {
+        for node in &program.body {
+            if let Flow::Return(value) = self.statement(node)? {
+                return match value {
+                    Value::Int(int) => Ok(int),
+                    _ => unreachable!("top-level return is checked to be an int"),
+                };
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Interprets `program`, writing everything it prints to `out`.
+///
+/// The interpreter runs on its own thread, whose stack is large enough for
+/// `MAX_CALL_DEPTH` nested calls, so deep recursion ends in a runtime error
+/// rather than a native stack overflow.
+pub fn run<W: Write + Send>(program: &CheckedProgram, out: W) -> Result<i64, RuntimeError> {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name(String::from("interpreter"))
+            .stack_size(MAX_CALL_DEPTH * STACK_PER_CALL)
+            .spawn_scoped(scope, || Interpreter::new(program, out).run(program))
+            .expect("failed to spawn the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
@@ -0,0 +1,247 @@
+use super::{Fragment, LexError, Lexer, SourceLocation, Span, SpannedToken, Token};
+
+use std::ops::Range;
+
+
+/// What `Lexer` yields for every token.
+pub type LexResult = Result<SpannedToken, LexError>;
+
+/// Replaces the bytes `range` of the old source with `replacement`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = String::with_capacity(source.len() + self.replacement.len());
+        edited.push_str(&source[..self.range.start]);
+        edited.push_str(&self.replacement);
+        edited.push_str(&source[self.range.end..]);
+        edited
+    }
+}
+
+fn span(item: &LexResult) -> Span {
+    match item {
+        Ok(token) => token.span,
+        Err(error) => error.span,
+    }
+}
+
+fn shift_location(location: &mut SourceLocation, offset: isize, line: isize) {
+    location.offset = location.offset.wrapping_add_signed(offset);
+    location.line = location.line.wrapping_add_signed(line);
+}
+
+fn shift_span(span: &mut Span, offset: isize, line: isize) {
+    shift_location(&mut span.start, offset, line);
+    shift_location(&mut span.end, offset, line);
+}
+
+/// Moves a token, and the tokens embedded in its string literal, if any.
+fn shift_token(token: &mut SpannedToken, offset: isize, line: isize) {
+    shift_span(&mut token.span, offset, line);
+    if let Token::Interpolation(fragments) = &mut token.token {
+        for fragment in fragments {
+            if let Fragment::Expr(tokens) = fragment {
+                for token in tokens {
+                    shift_token(token, offset, line);
+                }
+            }
+        }
+    }
+}
+
+fn shift(item: &mut LexResult, offset: isize, line: isize) {
+    match item {
+        Ok(token) => shift_token(token, offset, line),
+        Err(error) => shift_span(&mut error.span, offset, line),
+    }
+}
+
+/// Updates `tokens`, the output of `Lexer` over some source, after `edit`
+/// turned that source into `source`. Returns the range of `tokens` that was
+/// lexed again; everything else was kept and moved to its new location.
+///
+/// Between tokens the lexer remembers nothing but its location, so lexing
+/// from any token start gives the same tokens as lexing from the top. The
+/// update restarts at the last token that ends before the edit and stops as
+/// soon as it reaches the start of an old token past the edit on a later
+/// line, where line-relative columns can no longer differ. The result is
+/// therefore always identical to lexing `source` from scratch.
+pub fn relex(tokens: &mut Vec<LexResult>, source: &str, edit: &TextEdit) -> Range<usize> {
+    let removed = edit.range.end - edit.range.start;
+    let inserted = edit.replacement.len();
+    let offset_delta = inserted as isize - removed as isize;
+
+    let restart = tokens.iter()
+        .rposition(|item| matches!(item, Ok(token) if token.span.end.offset < edit.range.start))
+        .unwrap_or(0);
+    let start = match tokens.get(restart) {
+        Some(item) if restart > 0 => span(item).start,
+        // nothing safely precedes the edit, so start over from the top of the file
+        _ => SourceLocation {
+            file: tokens.first().map(|item| span(item).start.file).unwrap_or(0),
+            line: 1,
+            column: 1,
+            utf16_column: 1,
+            offset: 0,
+        },
+    };
+    let edit_end = edit.range.start + inserted;
+    let edit_end_line = start.line + source[start.offset..edit_end].matches('\n').count();
+
+    let mut fresh = Vec::new();
+    let mut resync = None;
+    let mut candidate = restart;
+    for item in Lexer::resume(source, start) {
+        if let Ok(token) = &item {
+            if token.span.start.line > edit_end_line {
+                // where this token started before the edit
+                let old_offset = token.span.start.offset + removed - inserted;
+                while candidate < tokens.len() && span(&tokens[candidate]).start.offset < old_offset {
+                    candidate += 1;
+                }
+                if let Some(Ok(old)) = tokens.get(candidate) {
+                    if old.span.start.offset == old_offset && old.token == token.token {
+                        let line_delta = token.span.start.line as isize - old.span.start.line as isize;
+                        resync = Some((candidate, line_delta));
+                        break;
+                    }
+                }
+            }
+        }
+        fresh.push(item);
+    }
+
+    let end = match resync {
+        Some((index, line_delta)) => {
+            for item in &mut tokens[index..] {
+                shift(item, offset_delta, line_delta);
+            }
+            index
+        },
+        None => tokens.len(),
+    };
+    let relexed = restart..restart + fresh.len();
+    tokens.splice(restart..end, fresh);
+    relexed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Covers the tokens whose extent an edit can change the most: strings
+    /// spanning lines, raw strings, interpolations, block comments and text
+    /// the lexer rejects.
+    const SOURCE: &str = r##"let name: str = "jet"
+let banner: str = """
+    hello {name}
+      escaped \{ brace
+    """
+let raw: str = r#"C:\path "quoted""#
+/* a block comment
+   over two lines */
+print("{name} has {1 + 2} parts")
+let broken: char = 'ab'
+print(banner) // done
+"##;
+
+    /// Pieces of syntax that open or close the constructs above.
+    const PIECES: &[&str] = &[
+        "\n", " ", "\"", "'", "/*", "*/", "//", "{", "}", "\\", "\"\"\"", "\"\"\"\n",
+        "r\"", "r#\"", "\"#", "{x}", "\\{", "let", "x", "1.5", "é", "😀",
+    ];
+
+    /// A fixed xorshift generator, so failures can be reproduced.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+    }
+
+    fn char_boundary(source: &str, mut offset: usize) -> usize {
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// Replaces `removed` bytes starting at the first `needle` in `source`.
+    fn edit_at(source: &str, needle: &str, removed: usize, replacement: &str) -> TextEdit {
+        let start = source.find(needle).expect("needle occurs in the source");
+        TextEdit { range: start..start + removed, replacement: String::from(replacement) }
+    }
+
+    fn assert_relexes(tokens: &mut Vec<LexResult>, source: &str, edit: &TextEdit) -> String {
+        let edited = edit.apply(source);
+        relex(tokens, &edited, edit);
+        assert_eq!(*tokens, Lexer::new(&edited).collect::<Vec<LexResult>>(), "after {:?} on {:?}", edit, edited);
+        edited
+    }
+
+    #[test]
+    fn source_contains_a_lexer_error() {
+        assert!(Lexer::new(SOURCE).any(|item| item.is_err()));
+    }
+
+    #[test]
+    fn relex_matches_full_lex_after_edits() {
+        let edits = [
+            // opens a string that swallows the rest of the line
+            edit_at(SOURCE, "jet", 0, "\""),
+            edit_at(SOURCE, "hello", 0, "more\n    "),
+            // removes the closing quotes, leaving the multi-line string unterminated
+            edit_at(SOURCE, "    \"\"\"\nlet raw", 7, ""),
+            edit_at(SOURCE, "{name}\n", 5, "name + 1"),
+            edit_at(SOURCE, "\\{", 1, ""),
+            edit_at(SOURCE, "\"#", 0, "#"),
+            edit_at(SOURCE, "r#", 1, "\n"),
+            edit_at(SOURCE, "*/", 2, ""),
+            edit_at(SOURCE, "/*", 0, "*/ "),
+            edit_at(SOURCE, "{1 + 2}", 7, "{\"nested {name}\"}"),
+            edit_at(SOURCE, "'ab'", 4, "'a'"),
+            edit_at(SOURCE, "// done", 0, "'"),
+            TextEdit { range: 0..0, replacement: String::from("/* ") },
+            TextEdit { range: SOURCE.len()..SOURCE.len(), replacement: String::from("\"\"\"\n") },
+            TextEdit { range: 0..SOURCE.len(), replacement: String::new() },
+        ];
+        for edit in &edits {
+            let mut tokens = Lexer::new(SOURCE).collect::<Vec<LexResult>>();
+            assert_relexes(&mut tokens, SOURCE, edit);
+        }
+    }
+
+    #[test]
+    fn relex_matches_full_lex_after_random_edits() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut source = String::from(SOURCE);
+        let mut tokens = Lexer::new(&source).collect::<Vec<LexResult>>();
+        for _ in 0..2000 {
+            let start = char_boundary(&source, rng.below(source.len() + 1));
+            let end = char_boundary(&source, (start + rng.below(6)).min(source.len()));
+            let mut replacement = String::new();
+            for _ in 0..rng.below(3) {
+                replacement.push_str(PIECES[rng.below(PIECES.len())]);
+            }
+            let edit = TextEdit { range: start..end, replacement };
+            source = assert_relexes(&mut tokens, &source, &edit);
+            if source.len() > 2000 {
+                source = String::from(SOURCE);
+                tokens = Lexer::new(&source).collect();
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use super::{LexError, Lexer, Span, Token, Trivia};
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LosslessKind {
+    Token(Token),
+    /// Text the lexer rejected; it is kept so the source can still be rebuilt.
+    Error(LexError),
+    /// The end of the source, carrying any trailing trivia.
+    Eof,
+}
+
+/// A token together with the trivia in front of it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LosslessToken {
+    pub leading_trivia: Vec<Trivia>,
+    pub kind: LosslessKind,
+    pub span: Span,
+}
+
+impl LosslessToken {
+    /// The span of the token including its leading trivia.
+    pub fn full_span(&self) -> Span {
+        match self.leading_trivia.first() {
+            Some(trivia) => Span { start: trivia.span.start, end: self.span.end },
+            None => self.span,
+        }
+    }
+
+    /// The source text of the token including its leading trivia.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        let span = self.full_span();
+        &source[span.start.offset..span.end.offset]
+    }
+}
+
+/// Streams every byte of the source as tokens with attached trivia, so
+/// that concatenating the `text` of all of them gives back the input.
+/// The last token is always `LosslessKind::Eof`.
+pub struct LosslessLexer<'a> {
+    lexer: Lexer<'a>,
+    done: bool,
+}
+
+impl<'a> LosslessLexer<'a> {
+    pub fn new(source: &'a str) -> LosslessLexer<'a> {
+        LosslessLexer {
+            lexer: Lexer::new(source),
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for LosslessLexer<'a> {
+    type Item = LosslessToken;
+
+    fn next(&mut self) -> Option<LosslessToken> {
+        if self.done {
+            return None;
+        }
+        let mut leading_trivia = Vec::new();
+        loop {
+            match self.lexer.trivia() {
+                Ok(Some(trivia)) => leading_trivia.push(trivia),
+                Ok(None) => break,
+                // only an unterminated block comment fails here, and it runs to the end
+                Err(error) => {
+                    let span = error.span;
+                    return Some(LosslessToken { leading_trivia, kind: LosslessKind::Error(error), span });
+                },
+            }
+        }
+
+        let start = self.lexer.location();
+        if self.lexer.peek().is_none() {
+            self.done = true;
+            return Some(LosslessToken {
+                leading_trivia,
+                kind: LosslessKind::Eof,
+                span: Span { start, end: start },
+            });
+        }
+        // errors may point at part of the token, but the token covers all that was consumed
+        let kind = match self.lexer.token(start) {
+            Ok(token) => LosslessKind::Token(token),
+            Err(error) => LosslessKind::Error(error),
+        };
+        Some(LosslessToken {
+            leading_trivia,
+            kind,
+            span: Span { start, end: self.lexer.location() },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strings spanning lines, raw strings, interpolations, comments and
+    /// text the lexer rejects, each with trivia around it.
+    const SOURCE: &str = r##"let name: str = "jet"
+let banner: str = """
+    hello {name}
+      escaped \{ brace
+    """
+let raw: str = r#"C:\path "quoted""#
+/* a block comment
+   over two lines */
+print("{name} has {1 + 2} parts")
+let broken: char = 'ab' @
+print(banner) // done
+"##;
+
+    fn assert_lossless(source: &str) {
+        let rebuilt = LosslessLexer::new(source)
+            .map(|token| token.text(source))
+            .collect::<String>();
+        assert_eq!(rebuilt, source);
+    }
+
+    #[test]
+    fn lossless_lexer_rebuilds_the_source() {
+        assert_lossless(SOURCE);
+        assert_lossless("");
+        assert_lossless("  \n\t// only trivia");
+        assert_lossless("let s: str = \"unterminated\nprint(s) /* open");
+        assert_lossless("\"\"\"\n  no closing quotes");
+    }
+
+    #[test]
+    fn lossless_lexer_rebuilds_every_prefix() {
+        // cutting the source short leaves every construct in it unterminated somewhere
+        for (end, _) in SOURCE.char_indices() {
+            assert_lossless(&SOURCE[..end]);
+        }
+    }
+
+    #[test]
+    fn errors_and_trailing_trivia_are_kept() {
+        let source = "1 @ 2 // done\n";
+        let tokens = LosslessLexer::new(source).collect::<Vec<LosslessToken>>();
+        let kinds = tokens.iter().map(|token| &token.kind).collect::<Vec<&LosslessKind>>();
+        assert!(matches!(kinds.as_slice(), [
+            LosslessKind::Token(Token::Int(1)),
+            LosslessKind::Error(_),
+            LosslessKind::Token(Token::Int(2)),
+            LosslessKind::Token(Token::Newline),
+            LosslessKind::Eof,
+        ]));
+        assert_eq!(tokens[1].text(source), " @");
+        assert_eq!(tokens[3].text(source), " // done\n");
+        assert_eq!(tokens[4].text(source), "");
+    }
+}
@@ -1,10 +1,13 @@
+mod lossless;
+mod incremental;
+
+pub use lossless::{LosslessKind, LosslessLexer, LosslessToken};
+pub use incremental::{relex, LexResult, TextEdit};
+
+use crate::symbol::{Interner, Symbol};
+
+use std::fmt;
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
-pub enum Mode {
-    Normal,
-    Str,
-    Comment,
-}
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Keyword {
@@ -12,9 +15,15 @@ pub enum Keyword {
     Let,
     Else,
     While,
+    For,
+    In,
+    Break,
+    Continue,
     Return,
     Use,
     Function,
+    Include,
+    Link,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -22,6 +31,9 @@ pub enum Type {
     Void,
     Int,
     Char,
+    Float,
+    /// `*int`; never produced by the lexer, the parser builds it from `*` and a type.
+    Pointer(Box<Type>),
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -30,7 +42,16 @@ pub enum Operator {
     Sub,
     Mul,
     Div,
+    Mod,
     And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    LogicalAnd,
+    LogicalOr,
+    Not,
+    BitNot,
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -39,16 +60,23 @@ pub enum Comparison {
     NotEqual,
     Bigger,
     Smaller,
-    Nop,
+    BiggerEqual,
+    SmallerEqual,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Keyword(Keyword),
     Type(Type),
-    Ident(String),
+    Ident(Symbol),
+    /// A loop label such as `'outer`, without the quote.
+    Label(Symbol),
     Int(usize),
-    Str(String),
+    Float(f64),
+    Str(Symbol),
+    Interpolation(Vec<Fragment>),
+    Char(char),
+    DocComment(Symbol),
     Operator(Operator),
     Comparison(Comparison),
 
@@ -65,53 +93,151 @@ pub enum Token {
     Comma,
     Colon,
     Equal,
+    DotDot,
+    DotDotEqual,
 
     Newline,
 }
 
-const STR_KEYWORDS: [&str; 33] = [
-    "[",
-    "]",
-    "{",
-    "}",
-    "(",
-    ")",
-    ";",
-    ",",
-    "=",
-    "==",
-    "!=",
-    "<",
-    ">",
-    "+",
-    "-",
-    "*",
-    "/",
-    ":",
-    "//",
-    "&",
-    "return",
-    "#include",
-    "#link",
-    "if",
-    "else",
-    "while",
-    "fn",
-    "let",
-    "int",
-    "char",
-    "void",
-    "\n",
-    " ",
-];
-
-pub type SourceLocation = (usize, usize);
-
-fn to_string(char_code: &Result<u8, std::io::Error>) -> String {
-    String::from_utf8(vec![*char_code.as_ref().unwrap()]).unwrap()
-}
-
-fn lex_token(token: &str) -> Token {
+/// A piece of a string literal with embedded `{...}` expressions.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Fragment {
+    Text(Symbol),
+    /// The tokens between the braces of an embedded expression.
+    Expr(Vec<SpannedToken>),
+}
+
+/// A position in the source. `column` counts characters from the start of
+/// the line, `utf16_column` counts UTF-16 code units as editors expect and
+/// `offset` is the exact byte offset; all columns start at 1. `file`
+/// identifies the source file when several are compiled together.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct SourceLocation {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub utf16_column: usize,
+    pub offset: usize,
+}
+
+/// The half-open byte range `start.offset..end.offset` a token covers.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct Span {
+    pub start: SourceLocation,
+    pub end: SourceLocation,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+}
+
+/// Source text between tokens that carries no meaning for the parser.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum LexErrorKind {
+    UnterminatedString,
+    TextAfterOpeningQuotes,
+    InsufficientIndent,
+    UnterminatedInterpolation,
+    EmptyInterpolation,
+    UnterminatedComment,
+    UnknownCharacter(char),
+    UnknownDirective,
+    MalformedNumber,
+    IntegerOutOfRange(&'static str),
+    FloatOutOfRange,
+    InvalidEscape(char),
+    MalformedEscape(char),
+    UnterminatedChar,
+    EmptyChar,
+    OverlongChar,
+    NestingTooDeep,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            LexErrorKind::TextAfterOpeningQuotes => {
+                write!(f, "a multi-line string starts on the line after its opening `\"\"\"`")
+            },
+            LexErrorKind::InsufficientIndent => {
+                write!(f, "line is indented less than the closing `\"\"\"` of its string")
+            },
+            LexErrorKind::UnterminatedInterpolation => {
+                write!(f, "unterminated `{{` in string literal, write `\\{{` for a literal brace")
+            },
+            LexErrorKind::EmptyInterpolation => write!(f, "expected an expression between `{{` and `}}`"),
+            LexErrorKind::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexErrorKind::UnknownCharacter(character) => {
+                write!(f, "unknown character `{}`", character.escape_debug())
+            },
+            LexErrorKind::UnknownDirective => write!(f, "unknown directive, expected `#include` or `#link`"),
+            LexErrorKind::MalformedNumber => write!(f, "malformed number literal"),
+            LexErrorKind::IntegerOutOfRange(typeid) => write!(f, "integer literal is out of range for `{}`", typeid),
+            LexErrorKind::FloatOutOfRange => write!(f, "float literal is out of range for `float`"),
+            LexErrorKind::InvalidEscape(character) => {
+                write!(f, "invalid escape sequence `\\{}`", character.escape_debug())
+            },
+            LexErrorKind::MalformedEscape('x') => {
+                write!(f, "`\\x` escapes take exactly two hex digits no greater than 7F")
+            },
+            LexErrorKind::MalformedEscape(character) => {
+                write!(f, "`\\{}` escapes take 1 to 6 hex digits in braces naming a Unicode scalar value", character)
+            },
+            LexErrorKind::UnterminatedChar => write!(f, "unterminated character literal"),
+            LexErrorKind::EmptyChar => write!(f, "empty character literal"),
+            LexErrorKind::OverlongChar => write!(f, "character literal may only contain one character"),
+            LexErrorKind::NestingTooDeep => {
+                write!(f, "string interpolations nest deeper than the limit of {} levels", MAX_NESTING)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::Xor => "^",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+            Operator::LogicalAnd => "&&",
+            Operator::LogicalOr => "||",
+            Operator::Not => "!",
+            Operator::BitNot => "~",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+fn lex_token(token: &str, interner: &mut Interner) -> Token {
     match token {
         "[" => Token::OpenBracket,
         "]" => Token::CloseBracket,
@@ -123,113 +249,1043 @@ fn lex_token(token: &str) -> Token {
         ":" => Token::Colon,
         "," => Token::Comma,
         "=" => Token::Equal,
+        ".." => Token::DotDot,
+        "..=" => Token::DotDotEqual,
         "+" => Token::Operator(Operator::Add),
         "-" => Token::Operator(Operator::Sub),
         "*" => Token::Operator(Operator::Mul),
         "/" => Token::Operator(Operator::Div),
+        "%" => Token::Operator(Operator::Mod),
         "&" => Token::Operator(Operator::And),
+        "|" => Token::Operator(Operator::Or),
+        "^" => Token::Operator(Operator::Xor),
+        "<<" => Token::Operator(Operator::Shl),
+        ">>" => Token::Operator(Operator::Shr),
+        "&&" => Token::Operator(Operator::LogicalAnd),
+        "||" => Token::Operator(Operator::LogicalOr),
+        "!" => Token::Operator(Operator::Not),
+        "~" => Token::Operator(Operator::BitNot),
         "==" => Token::Comparison(Comparison::Equal),
         "!=" => Token::Comparison(Comparison::NotEqual),
         "<" => Token::Comparison(Comparison::Smaller),
         ">" => Token::Comparison(Comparison::Bigger),
+        "<=" => Token::Comparison(Comparison::SmallerEqual),
+        ">=" => Token::Comparison(Comparison::BiggerEqual),
         "if" => Token::Keyword(Keyword::If),
         "let" => Token::Keyword(Keyword::Let),
         "else" => Token::Keyword(Keyword::Else),
         "while" => Token::Keyword(Keyword::While),
+        "for" => Token::Keyword(Keyword::For),
+        "in" => Token::Keyword(Keyword::In),
+        "break" => Token::Keyword(Keyword::Break),
+        "continue" => Token::Keyword(Keyword::Continue),
         "return" => Token::Keyword(Keyword::Return),
         "use" => Token::Keyword(Keyword::Use),
         "fn" => Token::Keyword(Keyword::Function),
+        "#include" => Token::Keyword(Keyword::Include),
+        "#link" => Token::Keyword(Keyword::Link),
         "int" => Token::Type(Type::Int),
+        "float" => Token::Type(Type::Float),
         "char" => Token::Type(Type::Char),
         "void" => Token::Type(Type::Void),
-        _ => {
-            let int = token.parse::<usize>();
-            if int.is_err() {
-                return Token::Ident(token.to_string());
-            } else {
-                return Token::Int(int.unwrap());
+        _ => Token::Ident(interner.intern(token)),
+    }
+}
+
+/// Splits a literal into its digits and the type named by its suffix,
+/// e.g. `0x41_char` into `0x41` and `char`.
+fn split_suffix(literal: &str) -> (&str, Type) {
+    for (suffix, typeid) in [("int", Type::Int), ("char", Type::Char), ("float", Type::Float)] {
+        if let Some(digits) = literal.strip_suffix(suffix) {
+            return (digits.trim_end_matches('_'), typeid);
+        }
+    }
+    (literal, Type::Int)
+}
+
+/// Ends the text fragment collected so far, if there is any.
+fn push_text(fragments: &mut Vec<Fragment>, text: &mut String, interner: &mut Interner) {
+    if !text.is_empty() {
+        fragments.push(Fragment::Text(interner.intern(text)));
+        text.clear();
+    }
+}
+
+/// Strings without embedded expressions stay plain `Token::Str`.
+fn string_token(mut fragments: Vec<Fragment>, mut text: String, interner: &mut Interner) -> Token {
+    if fragments.is_empty() {
+        return Token::Str(interner.intern(&text));
+    }
+    push_text(&mut fragments, &mut text, interner);
+    Token::Interpolation(fragments)
+}
+
+/// Identifiers follow Unicode UAX #31: an `XID_Start` character or `_`,
+/// then any number of `XID_Continue` characters.
+fn is_ident_start(character: char) -> bool {
+    character == '_' || unicode_ident::is_xid_start(character)
+}
+
+fn is_ident_continue(character: char) -> bool {
+    unicode_ident::is_xid_continue(character)
+}
+
+/// `///` starts a doc comment, but `////` is an ordinary comment again.
+fn is_doc_comment(rest: &str) -> bool {
+    rest.starts_with("///") && !rest.starts_with("////")
+}
+
+fn is_punctuation(character: char) -> bool {
+    "[]{}()<>;,=+-*/%:&|^!~".contains(character)
+}
+
+/// Whether two characters lex as a single operator such as `<=` or `&&`.
+fn is_double_punctuation(first: char, second: char) -> bool {
+    matches!(
+        (first, second),
+        ('=', '=') | ('!', '=') | ('<', '=') | ('>', '=') | ('<', '<') | ('>', '>') | ('&', '&') | ('|', '|')
+    )
+}
+
+/// Deepest nesting of string interpolations the lexer accepts, which keeps
+/// its recursion within the stack.
+const MAX_NESTING: usize = 64;
+
+/// Streams the tokens of a source string in a single pass.
+///
+/// Words and plain string literals are interned in a table the lexer owns,
+/// so lexing allocates only for text it has not seen before and for
+/// literals with escapes or embedded expressions. The table is dropped
+/// with the lexer; the text lives on only in the tokens.
+///
+/// Errors are yielded in place of the offending token and lexing resumes
+/// right after it.
+pub struct Lexer<'a> {
+    source: &'a str,
+    file: usize,
+    offset: usize,
+    line: usize,
+    column: usize,
+    utf16_column: usize,
+    /// How many interpolations enclose the token being lexed.
+    nesting: usize,
+    interner: Interner,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer::with_file(source, 0)
+    }
+
+    /// Lexes `source` as the file with the given id in a `SourceMap`.
+    pub fn with_file(source: &'a str, file: usize) -> Lexer<'a> {
+        Lexer {
+            source,
+            file,
+            offset: 0,
+            line: 1,
+            column: 1,
+            utf16_column: 1,
+            nesting: 0,
+            interner: Interner::default(),
+        }
+    }
+
+    /// Lexes `source` from `location`, which must be the start of a token.
+    fn resume(source: &'a str, location: SourceLocation) -> Lexer<'a> {
+        Lexer {
+            source,
+            file: location.file,
+            offset: location.offset,
+            line: location.line,
+            column: location.column,
+            utf16_column: location.utf16_column,
+            nesting: 0,
+            interner: Interner::default(),
+        }
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        SourceLocation {
+            file: self.file,
+            line: self.line,
+            column: self.column,
+            utf16_column: self.utf16_column,
+            offset: self.offset,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.rest().chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let character = self.peek()?;
+        self.offset += character.len_utf8();
+        if character == '\n' {
+            self.line += 1;
+            self.column = 1;
+            self.utf16_column = 1;
+        } else {
+            self.column += 1;
+            self.utf16_column += character.len_utf16();
+        }
+        Some(character)
+    }
+
+    fn skip_line(&mut self) {
+        // the newline itself is still a token
+        while !matches!(self.peek(), None | Some('\n')) {
+            self.bump();
+        }
+    }
+
+    /// Skips a `/* ... */` comment, which may nest.
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let start = self.location();
+        let mut depth = 0;
+        loop {
+            if self.rest().starts_with("/*") {
+                self.bump();
+                self.bump();
+                depth += 1;
+            } else if self.rest().starts_with("*/") {
+                self.bump();
+                self.bump();
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.bump().is_none() {
+                return Err(self.error(LexErrorKind::UnterminatedComment, start));
             }
-        },
-    }
-}
-
-pub fn tokenize(source: &str) -> Vec<(Token, SourceLocation)> {
-    let mut mode = Mode::Normal;
-    let mut tokens: Vec<(Token, SourceLocation)> = Vec::new();
-    let mut token = String::new();
-    let mut index = 0;
-    let mut line = 1;
-    let mut column = 1;
-    let bytes = source.chars().collect::<Vec<char>>();
-
-    while index < source.len() {
-        let character = &bytes[index].to_string();
-        if &(index + 1) < &bytes.len() {
-            if mode == Mode::Normal {
-                if character.as_str() == "=" {
-                    if &bytes[index + 1].to_string() == "=" {
-                        token = token + "==";
-                        index += 1;
-                    } else {
-                        token = token + &character;
-                    }
-                } else if character.as_str() == "!" {
-                    if &bytes[index + 1].to_string() == "=" {
-                        token = token + "!=";
-                        index += 1;
-                    } else {
-                        token = token + &character;
-                    }
-                } else if character.as_str() == "/" {
-                    if &bytes[index + 1].to_string() == "/" {
-                        mode = Mode::Comment;
-                        index += 1;
-                    } else {
-                        token = token + &character;
+        }
+    }
+
+    /// Consumes the next piece of trivia, if the source continues with one.
+    fn trivia(&mut self) -> Result<Option<Trivia>, LexError> {
+        let start = self.location();
+        let kind = match self.peek() {
+            Some(' ') | Some('\t') | Some('\r') => {
+                while matches!(self.peek(), Some(' ') | Some('\t') | Some('\r')) {
+                    self.bump();
+                }
+                TriviaKind::Whitespace
+            },
+            Some('/') if self.peek_second() == Some('/') && !is_doc_comment(self.rest()) => {
+                self.skip_line();
+                TriviaKind::LineComment
+            },
+            Some('/') if self.peek_second() == Some('*') => {
+                self.skip_block_comment()?;
+                TriviaKind::BlockComment
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(Trivia {
+            kind,
+            span: Span { start, end: self.location() },
+        }))
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), LexError> {
+        while self.trivia()?.is_some() {}
+        Ok(())
+    }
+
+    fn doc_comment(&mut self) -> Token {
+        let start = self.offset + "///".len();
+        self.skip_line();
+        let text = &self.source[start..self.offset];
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        Token::DocComment(self.interner.intern(text.trim_end_matches('\r')))
+    }
+
+    fn error(&self, kind: LexErrorKind, start: SourceLocation) -> LexError {
+        LexError {
+            kind,
+            span: Span { start, end: self.location() },
+        }
+    }
+
+    /// Consumes up to `limit` hex digits and returns them.
+    fn hex_digits(&mut self, limit: usize) -> &'a str {
+        let start = self.offset;
+        while self.offset - start < limit && self.peek().is_some_and(|character| character.is_ascii_hexdigit()) {
+            self.bump();
+        }
+        &self.source[start..self.offset]
+    }
+
+    /// Lexes the escape sequence starting at the current backslash. The
+    /// caller makes sure the backslash is followed by something on its line.
+    fn escape(&mut self) -> Result<char, LexError> {
+        let start = self.location();
+        self.bump();
+        let escaped = match self.bump() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('{') => '{',
+            Some('}') => '}',
+            Some('x') => {
+                // a single byte has to stay valid UTF-8 on its own; any
+                // hex digits after the first two are ordinary text
+                let digits = self.hex_digits(2);
+                match u8::from_str_radix(digits, 16) {
+                    Ok(byte) if digits.len() == 2 && byte <= 0x7F => byte as char,
+                    _ => return Err(self.error(LexErrorKind::MalformedEscape('x'), start)),
+                }
+            },
+            Some('u') => {
+                let mut scalar = None;
+                if self.peek() == Some('{') {
+                    self.bump();
+                    let digits = self.hex_digits(6);
+                    if self.peek() == Some('}') && (1..=6).contains(&digits.len()) {
+                        self.bump();
+                        scalar = u32::from_str_radix(digits, 16).ok().and_then(char::from_u32);
                     }
-                } else if character.as_str() == "\"" {
-                    mode = Mode::Str;
-                } else if character != " " {
-                    token = token + &character;
                 }
-                if STR_KEYWORDS.contains(&bytes[index + 1].to_string().as_str()) || STR_KEYWORDS.contains(&token.as_str()) {
-                    if token == "\n" {
-                        token = String::new();
-                    } else if token != "" {
-                        tokens.push((lex_token(&token), (line, column - (token.len() - 1))));
-                        token = String::new();
+                match scalar {
+                    Some(scalar) => scalar,
+                    None => return Err(self.error(LexErrorKind::MalformedEscape('u'), start)),
+                }
+            },
+            Some(character) => return Err(self.error(LexErrorKind::InvalidEscape(character), start)),
+            None => unreachable!("escape() is only called before a character"),
+        };
+        Ok(escaped)
+    }
+
+    /// Whether the current backslash is the last thing on its line.
+    fn at_dangling_backslash(&self) -> bool {
+        matches!(self.peek_second(), None | Some('\n'))
+    }
+
+    /// Lexes a string literal, which may embed `{...}` expressions; the first
+    /// bad escape is reported once the whole literal has been consumed.
+    fn string(&mut self, start: SourceLocation) -> Result<Token, LexError> {
+        self.bump();
+        let mut fragments = Vec::new();
+        let mut string = String::new();
+        // plain text stays in the source until an escape or an embedded
+        // expression forces it to be copied
+        let mut plain = self.offset;
+        let mut error = None;
+        loop {
+            match self.peek() {
+                Some('"') => break,
+                // plain strings end at the line, so a missing quote only costs one line
+                None | Some('\n') => return Err(self.error(LexErrorKind::UnterminatedString, start)),
+                Some('\\') if self.at_dangling_backslash() => {
+                    self.bump();
+                    return Err(self.error(LexErrorKind::UnterminatedString, start));
+                },
+                Some('\\') => {
+                    string.push_str(&self.source[plain..self.offset]);
+                    match self.escape() {
+                        Ok(character) => string.push(character),
+                        Err(escape) => {
+                            error.get_or_insert(escape);
+                        },
                     }
+                    plain = self.offset;
+                },
+                Some('{') => {
+                    string.push_str(&self.source[plain..self.offset]);
+                    let tokens = self.interpolation(&mut error)?;
+                    push_text(&mut fragments, &mut string, &mut self.interner);
+                    fragments.push(Fragment::Expr(tokens));
+                    plain = self.offset;
+                },
+                Some(_) => {
+                    self.bump();
+                },
+            }
+        }
+        let rest = &self.source[plain..self.offset];
+        self.bump();
+        match error {
+            Some(error) => Err(error),
+            None if fragments.is_empty() && string.is_empty() => Ok(Token::Str(self.interner.intern(rest))),
+            None => {
+                string.push_str(rest);
+                Ok(string_token(fragments, string, &mut self.interner))
+            },
+        }
+    }
+
+    /// Lexes an embedded `{...}` expression and returns its tokens. Errors
+    /// inside the expression go to `error`; the embedded expression itself
+    /// fails only when its braces do not close on the same line.
+    fn interpolation(&mut self, error: &mut Option<LexError>) -> Result<Vec<SpannedToken>, LexError> {
+        let start = self.location();
+        if self.nesting == MAX_NESTING {
+            // skip the expression as far as its braces match instead of lexing it
+            let mut depth = 0;
+            loop {
+                match self.peek() {
+                    None | Some('\n') => return Err(self.error(LexErrorKind::UnterminatedInterpolation, start)),
+                    Some('{') => depth += 1,
+                    Some('}') if depth == 1 => break,
+                    Some('}') => depth -= 1,
+                    Some(_) => {},
                 }
-            } else if mode == Mode::Str {
-                if character.as_str() == "\"" {
-                    mode = Mode::Normal;
-                    tokens.push((Token::Str(token.clone()), (line, column)));
-                    token = String::new();
-                } else if character.as_str() == "\\" {
-                    if &bytes[index + 1].to_string() == "n" {
-                        token = token + "\n";
-                        index += 1;
-                    } else {
-                      token = token + &character;
+                self.bump();
+            }
+            self.bump();
+            error.get_or_insert(self.error(LexErrorKind::NestingTooDeep, start));
+            return Ok(Vec::new());
+        }
+        self.nesting += 1;
+        let tokens = self.interpolation_tokens(start, error);
+        self.nesting -= 1;
+        tokens
+    }
+
+    /// Lexes an embedded expression that is not nested too deeply.
+    fn interpolation_tokens(
+        &mut self,
+        start: SourceLocation,
+        error: &mut Option<LexError>,
+    ) -> Result<Vec<SpannedToken>, LexError> {
+        self.bump();
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            if let Err(trivia) = self.skip_trivia() {
+                error.get_or_insert(trivia);
+            }
+            match self.peek() {
+                None | Some('\n') => return Err(self.error(LexErrorKind::UnterminatedInterpolation, start)),
+                Some('}') if depth == 0 => {
+                    self.bump();
+                    break;
+                },
+                _ => {},
+            }
+            let token_start = self.location();
+            match self.token(token_start) {
+                Ok(token) => {
+                    match token {
+                        Token::OpenBrace => depth += 1,
+                        Token::CloseBrace => depth -= 1,
+                        _ => {},
                     }
-                } else {
-                    token = token + &character;
+                    tokens.push(SpannedToken {
+                        token,
+                        span: Span { start: token_start, end: self.location() },
+                    });
+                },
+                Err(inner) => {
+                    error.get_or_insert(inner);
+                },
+            }
+        }
+        if tokens.is_empty() {
+            error.get_or_insert(self.error(LexErrorKind::EmptyInterpolation, start));
+        }
+        Ok(tokens)
+    }
+
+    /// Consumes `length` bytes of the source.
+    fn skip(&mut self, length: usize) {
+        let end = self.offset + length;
+        while self.offset < end {
+            self.bump();
+        }
+    }
+
+    fn at_raw_string(&self) -> bool {
+        self.rest()[1..].trim_start_matches('#').starts_with('"')
+    }
+
+    /// Lexes a raw string, `r"..."` or `r#"..."#` with any number of `#`.
+    /// Nothing inside is an escape and the string may span lines.
+    fn raw_string(&mut self, start: SourceLocation) -> Result<Token, LexError> {
+        self.bump();
+        let mut closing = String::from("\"");
+        while self.peek() == Some('#') {
+            self.bump();
+            closing.push('#');
+        }
+        self.bump();
+        let rest = self.rest();
+        match rest.find(&closing) {
+            Some(length) => {
+                self.skip(length + closing.len());
+                Ok(Token::Str(self.interner.intern(&rest[..length])))
+            },
+            None => {
+                self.skip(rest.len());
+                Err(self.error(LexErrorKind::UnterminatedString, start))
+            },
+        }
+    }
+
+    /// Lexes a multi-line string: `"""` at the end of a line, then lines of
+    /// text up to a line that starts with the closing `"""`. Whatever
+    /// indents the closing quotes is removed from every line, lines of only
+    /// whitespace become empty, the line breaks after the opening and before
+    /// the closing quotes are not part of the string, and a `\` at the end of
+    /// a line joins it with the next. Escapes and embedded expressions work
+    /// as in plain strings.
+    fn multiline_string(&mut self, start: SourceLocation) -> Result<Token, LexError> {
+        self.skip(3);
+        let mut error = None;
+        let opening = self.location();
+        while matches!(self.peek(), Some(' ' | '\t' | '\r')) {
+            self.bump();
+        }
+        if !matches!(self.peek(), None | Some('\n')) {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+            error = Some(self.error(LexErrorKind::TextAfterOpeningQuotes, opening));
+        }
+        self.bump();
+
+        let indent = self.rest().split('\n').find_map(|line| {
+            let code = line.trim_start_matches([' ', '\t']);
+            code.starts_with("\"\"\"").then(|| &line[..line.len() - code.len()])
+        });
+        let indent = match indent {
+            Some(indent) => indent,
+            None => {
+                self.skip(self.rest().len());
+                return Err(self.error(LexErrorKind::UnterminatedString, start));
+            },
+        };
+
+        let mut fragments = Vec::new();
+        let mut string = String::new();
+        // no line break goes in front of the first line
+        let mut joined = true;
+        loop {
+            let line_start = self.location();
+            let line = self.rest().split('\n').next().unwrap_or_default();
+            let code = line.trim_start_matches([' ', '\t']);
+            if code.starts_with("\"\"\"") {
+                self.skip(line.len() - code.len() + 3);
+                break;
+            }
+            if !joined {
+                string.push('\n');
+            }
+            joined = false;
+
+            if code.trim_end_matches('\r').is_empty() {
+                self.skip(line.len() - code.len());
+            } else if line.starts_with(indent) {
+                self.skip(indent.len());
+            } else {
+                self.skip(line.len() - code.len());
+                error.get_or_insert(self.error(LexErrorKind::InsufficientIndent, line_start));
+            }
+            loop {
+                match self.peek() {
+                    None | Some('\n') => break,
+                    Some('\r') if self.peek_second() == Some('\n') => {
+                        self.bump();
+                    },
+                    Some('\\') if self.at_dangling_backslash() => {
+                        self.bump();
+                        joined = true;
+                    },
+                    Some('\\') => match self.escape() {
+                        Ok(character) => string.push(character),
+                        Err(escape) => {
+                            error.get_or_insert(escape);
+                        },
+                    },
+                    Some('{') => match self.interpolation(&mut error) {
+                        Ok(tokens) => {
+                            push_text(&mut fragments, &mut string, &mut self.interner);
+                            fragments.push(Fragment::Expr(tokens));
+                        },
+                        Err(interpolation) => {
+                            error.get_or_insert(interpolation);
+                        },
+                    },
+                    Some(character) => {
+                        self.bump();
+                        string.push(character);
+                    },
                 }
             }
+            // an embedded expression may have run past the closing line
+            if self.bump().is_none() {
+                return Err(self.error(LexErrorKind::UnterminatedString, start));
+            }
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(string_token(fragments, string, &mut self.interner)),
+        }
+    }
+
+    /// Whether the `'` at the cursor starts a label such as `'outer` rather
+    /// than a character literal such as `'a'`.
+    fn at_label(&self) -> bool {
+        let mut rest = self.rest()[1..].chars();
+        rest.next().is_some_and(is_ident_start)
+            && rest.find(|character| !is_ident_continue(*character)) != Some('\'')
+    }
+
+    fn label(&mut self, start: SourceLocation) -> Token {
+        self.bump();
+        while self.peek().is_some_and(is_ident_continue) {
+            self.bump();
+        }
+        Token::Label(self.interner.intern(&self.source[start.offset + 1..self.offset]))
+    }
+
+    fn character(&mut self, start: SourceLocation) -> Result<Token, LexError> {
+        self.bump();
+        let character = match self.peek() {
+            None | Some('\n') => return Err(self.error(LexErrorKind::UnterminatedChar, start)),
+            Some('\'') => {
+                self.bump();
+                return Err(self.error(LexErrorKind::EmptyChar, start));
+            },
+            Some('\\') if self.at_dangling_backslash() => {
+                self.bump();
+                return Err(self.error(LexErrorKind::UnterminatedChar, start));
+            },
+            Some('\\') => self.escape(),
+            Some(character) => {
+                self.bump();
+                Ok(character)
+            },
+        };
+        if self.peek() == Some('\'') {
+            self.bump();
+            return character.map(Token::Char);
+        }
+
+        // skip the rest of an overlong literal so lexing resumes after it
+        while !matches!(self.peek(), None | Some('\n') | Some('\'')) {
+            self.bump();
+        }
+        if self.peek() != Some('\'') {
+            return Err(self.error(LexErrorKind::UnterminatedChar, start));
+        }
+        self.bump();
+        character?;
+        Err(self.error(LexErrorKind::OverlongChar, start))
+    }
+
+    /// Consumes decimal digits and `_` separators.
+    fn decimal_digits(&mut self) {
+        while self.peek().is_some_and(|character| character.is_ascii_digit() || character == '_') {
+            self.bump();
+        }
+    }
+
+    /// Whether an exponent such as `e10` or `E-3` follows.
+    fn at_exponent(&self) -> bool {
+        let mut rest = self.rest().chars();
+        matches!(rest.next(), Some('e' | 'E')) && match rest.next() {
+            Some('+' | '-') => rest.next().is_some_and(|character| character.is_ascii_digit()),
+            next => next.is_some_and(|character| character.is_ascii_digit()),
+        }
+    }
+
+    /// Lexes a decimal float such as `1.5`, `.5` or `2e10`. `numeric` is the
+    /// length of the literal before anything that is not part of the number.
+    fn float(&self, text: &str, numeric: usize, start: SourceLocation) -> Result<Token, LexError> {
+        let digits = match split_suffix(text) {
+            (digits, Type::Float) => digits,
+            // `1.5int` names a type the literal does not have
+            _ => text,
+        };
+        if digits.len() > numeric {
+            return Err(self.error(LexErrorKind::MalformedNumber, start));
+        }
+        match digits.replace('_', "").parse::<f64>() {
+            Ok(float) if float.is_finite() => Ok(Token::Float(float)),
+            Ok(_) => Err(self.error(LexErrorKind::FloatOutOfRange, start)),
+            Err(_) => Err(self.error(LexErrorKind::MalformedNumber, start)),
         }
-        if character.as_str() == "\n" {
-            tokens.push((Token::Newline, (line, column)));
-            line += 1;
-            column = 1;
-            if mode != Mode::Str {
-                mode = Mode::Normal;
+    }
+
+    /// Lexes an integer literal: decimal, `0x`, `0o` or `0b` digits with
+    /// optional `_` separators, optionally ending in a type suffix.
+    fn number(&mut self, start: SourceLocation) -> Result<Token, LexError> {
+        let mut float = false;
+        if !matches!(self.rest().get(..2), Some("0x" | "0o" | "0b")) {
+            self.decimal_digits();
+            if self.peek() == Some('.') && self.peek_second().is_some_and(|character| character.is_ascii_digit()) {
+                self.bump();
+                self.decimal_digits();
+                float = true;
             }
-        } else {
-            column += 1;
+            if self.at_exponent() {
+                self.bump();
+                if matches!(self.peek(), Some('+' | '-')) {
+                    self.bump();
+                }
+                self.decimal_digits();
+                float = true;
+            }
+        }
+        let numeric = self.offset - start.offset;
+        while self.peek().is_some_and(is_ident_continue) {
+            self.bump();
+        }
+        let text = &self.source[start.offset..self.offset];
+        let (digits, suffix) = split_suffix(text);
+        if float || suffix == Type::Float {
+            return self.float(text, numeric, start);
+        }
+        let (radix, digits) = match digits.get(..2) {
+            Some("0x") => (16, &digits[2..]),
+            Some("0o") => (8, &digits[2..]),
+            Some("0b") => (2, &digits[2..]),
+            _ => (10, digits),
+        };
+
+        let mut value: Option<u64> = Some(0);
+        let mut empty = true;
+        for character in digits.chars().filter(|character| *character != '_') {
+            let digit = match character.to_digit(radix) {
+                Some(digit) => digit,
+                None => return Err(self.error(LexErrorKind::MalformedNumber, start)),
+            };
+            empty = false;
+            value = value
+                .and_then(|value| value.checked_mul(radix as u64))
+                .and_then(|value| value.checked_add(digit as u64));
+        }
+        if empty {
+            return Err(self.error(LexErrorKind::MalformedNumber, start));
+        }
+
+        match suffix {
+            Type::Char => match value.and_then(|value| u32::try_from(value).ok()).and_then(char::from_u32) {
+                Some(character) => Ok(Token::Char(character)),
+                None => Err(self.error(LexErrorKind::IntegerOutOfRange("char"), start)),
+            },
+            _ => match value.filter(|value| *value <= i64::MAX as u64) {
+                Some(int) => Ok(Token::Int(int as usize)),
+                None => Err(self.error(LexErrorKind::IntegerOutOfRange("int"), start)),
+            },
+        }
+    }
+
+    /// Lexes an identifier or keyword, including the leading `#` of a directive.
+    fn word(&mut self, start: SourceLocation) -> Token {
+        self.bump();
+        while self.peek().is_some_and(is_ident_continue) {
+            self.bump();
         }
-        index += 1;
+        lex_token(&self.source[start.offset..self.offset], &mut self.interner)
+    }
+
+    fn token(&mut self, start: SourceLocation) -> Result<Token, LexError> {
+        let character = match self.peek() {
+            Some(character) => character,
+            None => unreachable!("token() is only called before the end of input"),
+        };
+        let token = match (character, self.peek_second()) {
+            ('"', _) if self.rest().starts_with("\"\"\"") => return self.multiline_string(start),
+            ('"', _) => return self.string(start),
+            ('r', Some('"' | '#')) if self.at_raw_string() => return self.raw_string(start),
+            ('\'', _) if self.at_label() => self.label(start),
+            ('\'', _) => return self.character(start),
+            ('/', Some('/')) => self.doc_comment(),
+            ('0'..='9', _) | ('.', Some('0'..='9')) => return self.number(start),
+            ('.', Some('.')) => {
+                self.bump();
+                self.bump();
+                if self.peek() == Some('=') {
+                    self.bump();
+                }
+                lex_token(&self.source[start.offset..self.offset], &mut self.interner)
+            },
+            ('#', Some(next)) if is_ident_start(next) => match self.word(start) {
+                Token::Ident(_) => return Err(self.error(LexErrorKind::UnknownDirective, start)),
+                directive => directive,
+            },
+            (character, _) if is_ident_start(character) => self.word(start),
+            (first, Some(second)) if is_double_punctuation(first, second) => {
+                self.bump();
+                self.bump();
+                lex_token(&self.source[start.offset..self.offset], &mut self.interner)
+            },
+            ('\n', _) => {
+                self.bump();
+                Token::Newline
+            },
+            (character, _) if is_punctuation(character) => {
+                self.bump();
+                lex_token(&self.source[start.offset..self.offset], &mut self.interner)
+            },
+            (character, _) => {
+                self.bump();
+                return Err(self.error(LexErrorKind::UnknownCharacter(character), start));
+            },
+        };
+        Ok(token)
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<SpannedToken, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(error) = self.skip_trivia() {
+            return Some(Err(error));
+        }
+        self.peek()?;
+        let start = self.location();
+        Some(self.token(start).map(|token| SpannedToken {
+            token,
+            span: Span { start, end: self.location() },
+        }))
+    }
+}
+
+/// Tokenizes a whole source file, collecting every error instead of
+/// stopping at the first one.
+pub fn tokenize(source: &str) -> Result<Vec<SpannedToken>, Vec<LexError>> {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for result in Lexer::new(source) {
+        match result {
+            Ok(token) => tokens.push(token),
+            Err(error) => errors.push(error),
+        }
+    }
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
     }
-    return tokens;
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_kinds(source: &str) -> Vec<LexErrorKind> {
+        Lexer::new(source).filter_map(|item| item.err()).map(|error| error.kind).collect()
+    }
+
+    /// Lexes `literal`, a single string literal, into its value.
+    fn string_value(literal: &str) -> Result<String, LexErrorKind> {
+        let tokens = Lexer::new(literal).collect::<Vec<LexResult>>();
+        match tokens.as_slice() {
+            [Ok(SpannedToken { token: Token::Str(text), .. })] => Ok(text.as_str().to_string()),
+            [Err(error)] => Err(error.kind),
+            tokens => panic!("expected one string literal, got {:?}", tokens),
+        }
+    }
+
+    #[test]
+    fn escapes_in_strings() {
+        let escapes = [
+            (r#""\n""#, "\n"),
+            (r#""\t""#, "\t"),
+            (r#""\r""#, "\r"),
+            (r#""\0""#, "\0"),
+            (r#""\\""#, "\\"),
+            (r#""\"""#, "\""),
+            (r#""\'""#, "'"),
+            (r#""\{""#, "{"),
+            (r#""\}""#, "}"),
+            (r#""\x41""#, "A"),
+            (r#""\x7f""#, "\x7F"),
+            (r#""\u{41}""#, "A"),
+            (r#""\u{1F600}""#, "😀"),
+            (r#""\u{10FFFF}""#, "\u{10FFFF}"),
+        ];
+        for (literal, value) in escapes {
+            assert_eq!(string_value(literal), Ok(String::from(value)), "{}", literal);
+        }
+    }
+
+    #[test]
+    fn hex_escapes_take_exactly_two_digits() {
+        assert_eq!(string_value(r#""\x41BC""#), Ok(String::from("ABC")));
+        assert_eq!(string_value(r#""\x410""#), Ok(String::from("A0")));
+        assert_eq!(string_value(r#""\x41fade""#), Ok(String::from("Afade")));
+        for literal in [r#""\x4""#, r#""\x""#, r#""\xg1""#, r#""\x80""#, r#""\xFF""#] {
+            assert_eq!(string_value(literal), Err(LexErrorKind::MalformedEscape('x')), "{}", literal);
+        }
+    }
+
+    #[test]
+    fn malformed_escapes_are_errors() {
+        let malformed = [r#""\u41""#, r#""\u{}""#, r#""\u{1234567}""#, r#""\u{110000}""#, r#""\u{D800}""#, r#""\u{41""#];
+        for literal in malformed {
+            assert_eq!(string_value(literal), Err(LexErrorKind::MalformedEscape('u')), "{}", literal);
+        }
+        assert_eq!(string_value(r#""\q""#), Err(LexErrorKind::InvalidEscape('q')));
+        assert_eq!(string_value(r#""\ ""#), Err(LexErrorKind::InvalidEscape(' ')));
+    }
+
+    #[test]
+    fn escapes_in_character_literals() {
+        let chars = [(r"'\n'", '\n'), (r"'\''", '\''), (r"'\x41'", 'A'), (r"'\u{E9}'", 'é'), ("'a'", 'a')];
+        for (literal, value) in chars {
+            let tokens = Lexer::new(literal).map(|item| item.map(|token| token.token)).collect::<Vec<_>>();
+            assert_eq!(tokens, [Ok(Token::Char(value))], "{}", literal);
+        }
+        assert_eq!(error_kinds(r"'\x4'"), [LexErrorKind::MalformedEscape('x')]);
+        assert_eq!(error_kinds(r"'\q'"), [LexErrorKind::InvalidEscape('q')]);
+    }
+
+    #[test]
+    fn interpolations_nest_up_to_the_limit() {
+        let nested = |depth: usize| format!("{}1{}", "\"{".repeat(depth), "}\"".repeat(depth));
+        assert_eq!(error_kinds(&nested(MAX_NESTING)), []);
+        assert_eq!(error_kinds(&nested(MAX_NESTING + 1)), [LexErrorKind::NestingTooDeep]);
+        assert_eq!(error_kinds(&nested(50_000)), [LexErrorKind::NestingTooDeep]);
+    }
+
+    /// Lexes `source` into the tokens it recovered and the kind, line and
+    /// column of each error.
+    fn recovered(source: &str) -> (Vec<Token>, Vec<(LexErrorKind, usize, usize)>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for item in Lexer::new(source) {
+            match item {
+                Ok(token) => tokens.push(token.token),
+                Err(error) => errors.push((error.kind, error.span.start.line, error.span.start.column)),
+            }
+        }
+        (tokens, errors)
+    }
+
+    #[test]
+    fn unterminated_strings_end_at_their_line() {
+        let (tokens, errors) = recovered("let a = \"abc\nlet b = 1");
+        assert_eq!(errors, [(LexErrorKind::UnterminatedString, 1, 9)]);
+        assert_eq!(tokens, [
+            Token::Keyword(Keyword::Let),
+            Token::Ident(Symbol::new("a")),
+            Token::Equal,
+            Token::Newline,
+            Token::Keyword(Keyword::Let),
+            Token::Ident(Symbol::new("b")),
+            Token::Equal,
+            Token::Int(1),
+        ]);
+    }
+
+    #[test]
+    fn unknown_characters_are_skipped() {
+        let (tokens, errors) = recovered("1 @ 2 $\n3");
+        assert_eq!(errors, [(LexErrorKind::UnknownCharacter('@'), 1, 3), (LexErrorKind::UnknownCharacter('$'), 1, 7)]);
+        assert_eq!(tokens, [Token::Int(1), Token::Int(2), Token::Newline, Token::Int(3)]);
+    }
+
+    #[test]
+    fn malformed_literals_are_skipped_whole() {
+        let (tokens, errors) = recovered("12abc 0b102 \"\\q\" 3");
+        assert_eq!(errors, [
+            (LexErrorKind::MalformedNumber, 1, 1),
+            (LexErrorKind::MalformedNumber, 1, 7),
+            (LexErrorKind::InvalidEscape('q'), 1, 14),
+        ]);
+        assert_eq!(tokens, [Token::Int(3)]);
+    }
+
+    #[test]
+    fn tokenize_collects_every_error() {
+        let errors = tokenize("let a = \"abc\nlet b = 1 @ 2\nlet c = 12abc\n").expect_err("source has errors");
+        let kinds = errors.iter().map(|error| error.kind).collect::<Vec<LexErrorKind>>();
+        assert_eq!(kinds, [
+            LexErrorKind::UnterminatedString,
+            LexErrorKind::UnknownCharacter('@'),
+            LexErrorKind::MalformedNumber,
+        ]);
+        assert!(tokenize("let a = 1\n").is_ok());
+    }
+
+    /// Lexes `source`, a single literal, into its token.
+    fn literal(source: &str) -> Result<Token, LexErrorKind> {
+        let tokens = Lexer::new(source).collect::<Vec<LexResult>>();
+        match tokens.as_slice() {
+            [Ok(token)] => Ok(token.token.clone()),
+            [Err(error)] => Err(error.kind),
+            tokens => panic!("expected one literal, got {:?}", tokens),
+        }
+    }
 
+    #[test]
+    fn integer_literals_in_every_radix() {
+        let literals = [
+            ("42", 42),
+            ("0x2a", 42),
+            ("0x2A", 42),
+            ("0o52", 42),
+            ("0b101010", 42),
+            ("0", 0),
+            ("007", 7),
+            ("1_000_000", 1_000_000),
+            ("0xFF_FF", 0xFFFF),
+            ("0b1010_1010", 0b1010_1010),
+            ("0x_ff", 0xFF),
+            ("42int", 42),
+            ("42_int", 42),
+            ("0x2a_int", 42),
+        ];
+        for (source, value) in literals {
+            assert_eq!(literal(source), Ok(Token::Int(value)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn literal_suffixes_pick_the_type() {
+        assert_eq!(literal("0x41_char"), Ok(Token::Char('A')));
+        assert_eq!(literal("65char"), Ok(Token::Char('A')));
+        assert_eq!(literal("0x1F600char"), Ok(Token::Char('😀')));
+        assert_eq!(literal("2float"), Ok(Token::Float(2.0)));
+        assert_eq!(literal("1_000.5_float"), Ok(Token::Float(1000.5)));
+        assert_eq!(literal("1.5int"), Err(LexErrorKind::MalformedNumber));
+        assert_eq!(literal("42uint"), Err(LexErrorKind::MalformedNumber));
+    }
+
+    #[test]
+    fn integer_literals_out_of_range() {
+        assert_eq!(literal("9223372036854775807"), Ok(Token::Int(i64::MAX as usize)));
+        assert_eq!(literal("0x7FFF_FFFF_FFFF_FFFF"), Ok(Token::Int(i64::MAX as usize)));
+        assert_eq!(literal("0x10FFFF_char"), Ok(Token::Char('\u{10FFFF}')));
+        let int = LexErrorKind::IntegerOutOfRange("int");
+        let char = LexErrorKind::IntegerOutOfRange("char");
+        for (source, error) in [
+            ("9223372036854775808", int),
+            ("99999999999999999999999", int),
+            ("0x8000_0000_0000_0000", int),
+            ("0b1_0000000000_0000000000_0000000000_0000000000_0000000000_0000000000_000", int),
+            ("0x110000_char", char),
+            ("0xD800char", char),
+        ] {
+            assert_eq!(literal(source), Err(error), "{}", source);
+        }
+        let (_, errors) = recovered("let x = 99999999999999999999\n");
+        assert_eq!(errors, [(int, 1, 9)]);
+    }
+
+    #[test]
+    fn malformed_integer_literals() {
+        for source in ["0x", "0x_", "0xg", "0b2", "0o8", "0b", "12abc", "0x1g", "1_000x"] {
+            assert_eq!(literal(source), Err(LexErrorKind::MalformedNumber), "{}", source);
+        }
+    }
+}
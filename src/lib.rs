@@ -0,0 +1,18 @@
+//! The Jet compiler as a library.
+//!
+//! Nothing in here prints or exits the process; every stage reports
+//! problems as [`Diagnostic`]s and leaves presenting them to the caller.
+
+pub mod symbol;
+pub mod lexer;
+pub mod ast;
+pub mod diagnostic;
+pub mod driver;
+pub mod check;
+pub mod lower;
+pub mod interp;
+pub mod codegen;
+
+pub use diagnostic::Diagnostic;
+pub use symbol::Symbol;
+pub use driver::{compile, CheckedProgram, Program, SourceMap};
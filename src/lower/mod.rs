@@ -0,0 +1,270 @@
+use crate::lexer;
+use crate::ast::Node;
+use crate::symbol::Symbol;
+
+
+/// Rewrites a checked program body into the subset of nodes every backend
+/// runs: `for` loops become `while` loops over hidden counters.
+pub fn lower(body: Vec<Node>) -> Vec<Node> {
+    lower_nodes(body, 0)
+}
+
+/// `depth` counts the `for` loops around `body`.
+fn lower_nodes(body: Vec<Node>, depth: usize) -> Vec<Node> {
+    body.into_iter().map(|node| lower_node(node, depth)).collect()
+}
+
+fn lower_body(body: (Vec<Node>, lexer::SourceLocation), depth: usize) -> (Vec<Node>, lexer::SourceLocation) {
+    (lower_nodes(body.0, depth), body.1)
+}
+
+fn lower_node(node: Node, depth: usize) -> Node {
+    match node {
+        Node::Function { name, parameters, return_type, body, docs } => Node::Function {
+            name,
+            parameters,
+            return_type,
+            body: lower_body(body, depth),
+            docs,
+        },
+        Node::If { test, then_body, else_body } => Node::If {
+            test,
+            then_body: lower_body(then_body, depth),
+            else_body: lower_body(else_body, depth),
+        },
+        Node::While { label, test, body } => Node::While { label, test, body: lower_body(body, depth) },
+        Node::Block { body } => Node::Block { body: lower_body(body, depth) },
+        Node::For { label, variable, start, end, inclusive, body } => {
+            lower_for(depth, label, variable, start, end, inclusive, body)
+        },
+        node => node,
+    }
+}
+
+/// Builds the nodes lowering emits, all pointing at one source location.
+struct Builder {
+    location: lexer::SourceLocation,
+}
+
+impl Builder {
+    fn ident(&self, name: &Symbol) -> (Box<Node>, lexer::SourceLocation) {
+        (Box::new(Node::Ident(name.clone(), self.location)), self.location)
+    }
+
+    fn declare(&self, name: &Symbol, expr: (Box<Node>, lexer::SourceLocation)) -> Node {
+        Node::Variable {
+            name: (name.clone(), self.location),
+            var_type: (lexer::Type::Int, self.location),
+            expr,
+            docs: Vec::new(),
+        }
+    }
+
+    fn assign(&self, name: &Symbol, expr: Node) -> Node {
+        Node::Assign {
+            target: self.ident(name),
+            expr: (Box::new(expr), self.location),
+        }
+    }
+
+    fn compare(&self, left: &Symbol, op: lexer::Comparison, right: &Symbol) -> Node {
+        Node::Comparison {
+            lexpr: self.ident(left),
+            rexpr: self.ident(right),
+            op: (op, self.location),
+        }
+    }
+
+    fn increment(&self, name: &Symbol) -> Node {
+        self.assign(name, Node::BinaryExpr {
+            lexpr: self.ident(name),
+            rexpr: (Box::new(Node::Int(1, self.location)), self.location),
+            op: (lexer::Operator::Add, self.location),
+        })
+    }
+
+    /// The statements ending an iteration of a lowered `for` loop.
+    fn step(&self, counter: &Symbol, limit: &Symbol, more: Option<&Symbol>) -> Vec<Node> {
+        let mut step = Vec::new();
+        if let Some(more) = more {
+            step.push(self.assign(more, self.compare(counter, lexer::Comparison::Smaller, limit)));
+        }
+        step.push(self.increment(counter));
+        step
+    }
+}
+
+/// Lowers `for i in start..end { body }` to
+///
+/// ```text
+/// {
+///     let counter: int = start
+///     let end: int = end
+///     while counter < end {
+///         let i: int = counter
+///         { body }
+///         counter = counter + 1
+///     }
+/// }
+/// ```
+///
+/// The hidden names contain a space, so no program can refer to them, and
+/// end in the depth of the loop, so a step spliced into an inner loop still
+/// names the counter of the loop it belongs to. The
+/// loop variable is a fresh copy of the counter in every iteration, so
+/// assigning to it does not change how often the loop runs, and the body
+/// keeps a scope of its own in which it may shadow it. An inclusive
+/// range tests a flag instead, which is cleared before the counter would
+/// step past `end`; that way `..=` reaches the largest `int` without the
+/// counter wrapping around. A `continue` aimed at the loop runs the step
+/// before jumping back to the test.
+fn lower_for(
+    depth: usize,
+    label: Option<(Symbol, lexer::SourceLocation)>,
+    variable: (Symbol, lexer::SourceLocation),
+    start: (Box<Node>, lexer::SourceLocation),
+    end: (Box<Node>, lexer::SourceLocation),
+    inclusive: bool,
+    body: (Vec<Node>, lexer::SourceLocation),
+) -> Node {
+    let builder = Builder { location: variable.1 };
+    let counter = Symbol::new(&format!("for counter {}", depth));
+    let limit = Symbol::new(&format!("for end {}", depth));
+    let more = inclusive.then(|| Symbol::new(&format!("for more {}", depth)));
+    let step = || builder.step(&counter, &limit, more.as_ref());
+
+    let target = label.as_ref().map(|label| &label.0);
+    let iteration = retarget(lower_nodes(body.0, depth + 1), target, false, &step);
+    let mut loop_body = vec![
+        builder.declare(&variable.0, builder.ident(&counter)),
+        Node::Block { body: (iteration, body.1) },
+    ];
+    loop_body.extend(step());
+    let mut scope = vec![builder.declare(&counter, start), builder.declare(&limit, end)];
+    let test = match &more {
+        Some(more) => {
+            let first = builder.compare(&counter, lexer::Comparison::SmallerEqual, &limit);
+            scope.push(builder.declare(more, (Box::new(first), builder.location)));
+            Node::Ident(more.clone(), builder.location)
+        },
+        None => builder.compare(&counter, lexer::Comparison::Smaller, &limit),
+    };
+    scope.push(Node::While {
+        label,
+        test: (Box::new(test), builder.location),
+        body: (loop_body, body.1),
+    });
+    Node::Block { body: (scope, builder.location) }
+}
+
+/// Puts `step` in front of every `continue` in an already lowered loop body
+/// that targets the loop labeled `label`. `nested` is set inside the loops
+/// within that body, where only a labeled `continue` can reach out.
+fn retarget<F: Fn() -> Vec<Node>>(body: Vec<Node>, label: Option<&Symbol>, nested: bool, step: &F) -> Vec<Node> {
+    let retarget_body = |body: (Vec<Node>, lexer::SourceLocation), nested| {
+        (retarget(body.0, label, nested, step), body.1)
+    };
+    body.into_iter()
+        .map(|node| match node {
+            Node::Continue { label: target, location } if match &target {
+                Some(target) => Some(&target.0) == label,
+                None => !nested,
+            } => {
+                let mut jump = step();
+                jump.push(Node::Continue { label: target, location });
+                Node::Block { body: (jump, location) }
+            },
+            Node::If { test, then_body, else_body } => Node::If {
+                test,
+                then_body: retarget_body(then_body, nested),
+                else_body: retarget_body(else_body, nested),
+            },
+            Node::Block { body } => Node::Block { body: retarget_body(body, nested) },
+            // an inner loop with the same label hides this one
+            Node::While { label: inner, test, body }
+                if label.is_some() && inner.as_ref().map(|inner| &inner.0) != label =>
+            {
+                Node::While { label: inner, test, body: retarget_body(body, true) }
+            },
+            node => node,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver;
+
+    /// Parses `source`, puts a step that reads the variable `step` in front
+    /// of each `continue` aimed at the loop labeled `label` and prints the
+    /// result.
+    fn retargeted(source: &str, label: Option<&str>) -> String {
+        let body = driver::parse(source).expect("source parses").body;
+        let label = label.map(Symbol::new);
+        let step = || vec![Node::Ident(Symbol::new("step"), lexer::SourceLocation::default())];
+        render(&retarget(body, label.as_ref(), false, &step))
+    }
+
+    fn render(body: &[Node]) -> String {
+        let nodes = body.iter()
+            .map(|node| match node {
+                Node::Ident(name, _) => name.to_string(),
+                Node::Continue { label, .. } => jump("continue", label),
+                Node::Break { label, .. } => jump("break", label),
+                Node::Block { body } => format!("{{ {} }}", render(&body.0)),
+                Node::If { then_body, else_body, .. } => {
+                    format!("if {{ {} }} else {{ {} }}", render(&then_body.0), render(&else_body.0))
+                },
+                Node::While { label: Some(label), body, .. } => {
+                    format!("'{}: while {{ {} }}", label.0, render(&body.0))
+                },
+                Node::While { label: None, body, .. } => format!("while {{ {} }}", render(&body.0)),
+                node => panic!("unexpected node in a loop body: {:?}", node),
+            })
+            .collect::<Vec<String>>();
+        nodes.join(" ")
+    }
+
+    fn jump(keyword: &str, label: &Option<(Symbol, lexer::SourceLocation)>) -> String {
+        match label {
+            Some(label) => format!("{} '{}", keyword, label.0),
+            None => String::from(keyword),
+        }
+    }
+
+    #[test]
+    fn continue_runs_the_step_first() {
+        let source = "continue\nif 1 { continue } else { break }\n";
+        assert_eq!(retargeted(source, None), "{ step continue } if { { step continue } } else { break }");
+        assert_eq!(retargeted("continue 'outer\n", Some("outer")), "{ step continue 'outer }");
+    }
+
+    #[test]
+    fn unlabeled_continue_in_an_inner_loop_stays() {
+        assert_eq!(retargeted("while 1 { continue }\n", None), "while { continue }");
+        assert_eq!(retargeted("while 1 { continue }\n", Some("outer")), "while { continue }");
+    }
+
+    #[test]
+    fn labeled_continue_reaches_out_of_inner_loops() {
+        let source = "\
+'inner: while 1 {
+    while 1 {
+        if 1 { continue 'outer }
+        continue 'inner
+    }
+}
+";
+        assert_eq!(
+            retargeted(source, Some("outer")),
+            "'inner: while { while { if { { step continue 'outer } } else {  } continue 'inner } }",
+        );
+    }
+
+    #[test]
+    fn inner_loop_with_the_same_label_hides_the_outer_one() {
+        let source = "'outer: while 1 { continue 'outer }\ncontinue 'other\n";
+        assert_eq!(retargeted(source, Some("outer")), "'outer: while { continue 'outer } continue 'other");
+    }
+}
@@ -1,50 +1,379 @@
-mod lexer;
-mod ast;
+use jetlang::{codegen, driver, interp, lexer, CheckedProgram, Diagnostic, SourceMap};
 
-use std::fs::File;
-use std::io::BufReader;
-use std::io::prelude::*;
 use std::env;
-use std::process;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
 
 
-fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        println!("Err: Not enough arguments");
-        process::exit(1);
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_RUNTIME_ERROR: i32 = 101;
+
+const USAGE: &str = "\
+Usage: jet <command> [options] <file>
+
+Commands:
+    lex      Print the tokens of a source file
+    parse    Print the syntax tree of a source file
+    check    Report diagnostics without running the program
+    run      Interpret the program
+    build    Compile the program to a native executable
+
+Options:
+    -h, --help    Print help for jet or for a command
+
+Exit codes:
+    0      Success
+    1      The program has compile errors
+    2      Invalid command line
+    3      A file could not be read or written
+    101    The program failed at runtime
+";
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Subcommand {
+    Lex,
+    Parse,
+    Check,
+    Run,
+    Build,
+}
+
+impl Subcommand {
+    fn from_name(name: &str) -> Option<Subcommand> {
+        match name {
+            "lex" => Some(Subcommand::Lex),
+            "parse" => Some(Subcommand::Parse),
+            "check" => Some(Subcommand::Check),
+            "run" => Some(Subcommand::Run),
+            "build" => Some(Subcommand::Build),
+            _ => None,
+        }
     }
-    let fd = File::open(&args[1]);
-    if fd.is_err() {
-        println!("Err: Failed to open '{}': {:?}", &args[1], fd.unwrap_err());
-        process::exit(1);
+
+    fn usage(&self) -> &'static str {
+        match self {
+            Subcommand::Lex => "\
+Usage: jet lex [options] <file>
+
+Prints every token of <file> with its line, column and byte range.
+
+Options:
+    --trivia    Also print whitespace and comments, so that the listing
+                covers every byte of <file>
+",
+            Subcommand::Parse => "\
+Usage: jet parse <file>
+
+Prints the syntax tree of <file>.
+",
+            Subcommand::Check => "\
+Usage: jet check <file>
+
+Reports syntax and type errors in <file> without running it.
+",
+            Subcommand::Run => "\
+Usage: jet run <file>
+
+Interprets <file>. The exit code is the value of a top-level `return`,
+or 101 if the program fails at runtime.
+",
+            Subcommand::Build => "\
+Usage: jet build [options] <file>
+
+Compiles <file> to a native executable through the C compiler in $CC
+(default `cc`). The executable is named after <file> without its
+extension, or with `.out` appended if it has none; the C source is
+named after <file> with a `.c` extension. Neither may replace <file>.
+
+Options:
+    -o, --output <path>    Write the artifact to <path>
+    --emit-c               Write the generated C source instead of linking it
+",
+        }
     }
-    let reader = BufReader::new(fd.unwrap());
+}
+
+struct Options {
+    subcommand: Subcommand,
+    file: PathBuf,
+    output: Option<PathBuf>,
+    emit_c: bool,
+    trivia: bool,
+}
 
-    let mut line_buffer: String = String::new();
-    let mut indentation_counter: usize = 0;
+fn usage_error(message: &str, usage: &str) -> ! {
+    eprintln!("error: {}\n\n{}", message, usage);
+    process::exit(EXIT_USAGE);
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let subcommand = match args.first().map(String::as_str) {
+        None => usage_error("no command given", USAGE),
+        Some("-h") | Some("--help") | Some("help") => {
+            print!("{}", USAGE);
+            process::exit(EXIT_SUCCESS);
+        },
+        Some(name) => match Subcommand::from_name(name) {
+            Some(subcommand) => subcommand,
+            None => usage_error(&format!("unknown command `{}`", name), USAGE),
+        },
+    };
+    let usage = subcommand.usage();
 
-    for byte in reader.bytes() {
-        if byte.is_err() {
-            println!("Err: failed to read byte");
-            process::exit(1);
+    let mut file = None;
+    let mut output = None;
+    let mut emit_c = false;
+    let mut trivia = false;
+    let mut index = 1;
+    while index < args.len() {
+        match args[index].as_str() {
+            "-h" | "--help" => {
+                print!("{}", usage);
+                process::exit(EXIT_SUCCESS);
+            },
+            "-o" | "--output" if subcommand == Subcommand::Build => {
+                index += 1;
+                match args.get(index) {
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => usage_error("`--output` requires a path", usage),
+                }
+            },
+            "--emit-c" if subcommand == Subcommand::Build => emit_c = true,
+            "--trivia" if subcommand == Subcommand::Lex => trivia = true,
+            option if option.starts_with('-') => {
+                usage_error(&format!("unknown option `{}`", option), usage);
+            },
+            path => {
+                if file.is_some() {
+                    usage_error(&format!("unexpected argument `{}`", path), usage);
+                }
+                file = Some(PathBuf::from(path));
+            },
         }
-        let character = String::from_utf8(vec![byte.unwrap()]).unwrap();
-        if &character == "\n" && indentation_counter == 0 {
-            line_buffer = line_buffer + &character;
-            let tokens = lexer::tokenize(&line_buffer);
-            println!("Tokens: {:?}", tokens);
-            let tree = ast::build_ast(tokens);
-            println!("Ast: {:?}", tree);
-            line_buffer = String::new();
-            continue;
-        } else if &character == "{" {
-            indentation_counter += 1;
-        } else if &character == "}" {
-            indentation_counter -= 1;
+        index += 1;
+    }
+
+    match file {
+        Some(file) => Options { subcommand, file, output, emit_c, trivia },
+        None => usage_error("no input file given", usage),
+    }
+}
+
+fn io_error(action: &str, path: &Path, err: io::Error) -> ! {
+    eprintln!("error: failed to {} '{}': {}", action, path.display(), err);
+    process::exit(EXIT_IO);
+}
+
+/// Unwraps a front-end result, reporting its diagnostics and exiting on failure.
+fn report<T>(sources: &SourceMap, result: Result<T, Vec<Diagnostic>>) -> T {
+    match result {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}:{}", sources.path(diagnostic.location.file).display(), diagnostic);
+            }
+            process::exit(EXIT_COMPILE_ERROR);
+        },
+    }
+}
+
+/// Whether `output` names the file `input` was read from, which writing
+/// the artifact would destroy.
+fn same_file(input: &Path, output: &Path) -> bool {
+    match (fs::canonicalize(input), fs::canonicalize(output)) {
+        (Ok(input), Ok(output)) => input == output,
+        // a missing output cannot be the input, which was just read
+        _ => false,
+    }
+}
+
+/// Picks where `jet build` writes its artifact, `--output` or `default`,
+/// refusing paths that lead back to the input file.
+fn output_path(options: &Options, default: PathBuf) -> PathBuf {
+    let output = options.output.clone().unwrap_or(default);
+    if same_file(&options.file, &output) {
+        eprintln!("error: refusing to overwrite the input file '{}'", options.file.display());
+        process::exit(EXIT_USAGE);
+    }
+    output
+}
+
+/// Creates a C file in the temporary directory that no other file or
+/// concurrent build uses.
+fn temp_c_file() -> io::Result<(PathBuf, fs::File)> {
+    let directory = env::temp_dir();
+    for attempt in 0.. {
+        let path = directory.join(format!("jet-{}-{}.c", process::id(), attempt));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("the attempts never run out")
+}
+
+fn build(options: &Options, program: &CheckedProgram, sources: &SourceMap) {
+    let source = codegen::generate(program, sources);
+    if options.emit_c {
+        let output = output_path(options, options.file.with_extension("c"));
+        if let Err(err) = fs::write(&output, source) {
+            io_error("write", &output, err);
+        }
+        return;
+    }
+
+    // `jet build noext` must not replace its own source with the executable
+    let default = match options.file.extension() {
+        Some(_) => options.file.with_extension(""),
+        None => options.file.with_extension("out"),
+    };
+    let output = output_path(options, default);
+    let c_file = match temp_c_file() {
+        Ok((path, mut file)) => {
+            if let Err(err) = file.write_all(source.as_bytes()) {
+                let _ = fs::remove_file(&path);
+                io_error("write", &path, err);
+            }
+            path
+        },
+        Err(err) => io_error("create", &env::temp_dir(), err),
+    };
+    let compiler = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let status = Command::new(&compiler)
+        .arg("-O2")
+        .arg("-o")
+        .arg(&output)
+        .arg(&c_file)
+        .args(program.links.iter().map(|library| format!("-l{}", library)))
+        .status();
+    let _ = fs::remove_file(&c_file);
+    match status {
+        Ok(status) if status.success() => {},
+        Ok(status) => {
+            eprintln!("error: `{}` failed with {}", compiler, status);
+            process::exit(EXIT_COMPILE_ERROR);
+        },
+        Err(err) => {
+            eprintln!("error: failed to run `{}`: {}", compiler, err);
+            process::exit(EXIT_IO);
+        },
+    }
+}
+
+fn format_span(span: lexer::Span) -> String {
+    format!("{}:{}\t{}..{}", span.start.line, span.start.column, span.start.offset, span.end.offset)
+}
+
+/// Writes the listing lines of each token and reports the lexer errors
+/// among them, exiting with EXIT_COMPILE_ERROR if there were any.
+fn write_listing(
+    options: &Options,
+    listing: impl Iterator<Item = (Vec<String>, Option<lexer::LexError>)>,
+    mut out: impl Write,
+) {
+    let mut failed = false;
+    for (lines, error) in listing {
+        if let Some(error) = error {
+            eprintln!("{}:{}", options.file.display(), Diagnostic::from(error));
+            failed = true;
         }
-        line_buffer = line_buffer + &character;
+        // a closed pipe (e.g. `jet lex file | head`) is not an error
+        if lines.iter().any(|line| writeln!(out, "{}", line).is_err()) {
+            break;
+        }
+    }
+    if failed {
+        process::exit(EXIT_COMPILE_ERROR);
     }
 }
 
+fn lex_tokens(options: &Options, source: &str, out: impl Write) {
+    let listing = lexer::Lexer::new(source).map(|result| match result {
+        Ok(spanned) => (vec![format!("{}\t{:?}", format_span(spanned.span), spanned.token)], None),
+        Err(error) => (Vec::new(), Some(error)),
+    });
+    write_listing(options, listing, out);
+}
+
+fn lex_lossless(options: &Options, source: &str, out: impl Write) {
+    let listing = lexer::LosslessLexer::new(source).map(|token| {
+        let mut lines = token.leading_trivia.iter()
+            .map(|trivia| format!("{}\t{:?}", format_span(trivia.span), trivia.kind))
+            .collect::<Vec<String>>();
+        let span = format_span(token.span);
+        let error = match token.kind {
+            lexer::LosslessKind::Token(kind) => {
+                lines.push(format!("{}\t{:?}", span, kind));
+                None
+            },
+            lexer::LosslessKind::Error(error) => {
+                lines.push(format!("{}\tError", span));
+                Some(error)
+            },
+            lexer::LosslessKind::Eof => {
+                lines.push(format!("{}\tEof", span));
+                None
+            },
+        };
+        (lines, error)
+    });
+    write_listing(options, listing, out);
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let options = parse_args(&args);
+    let source = match driver::read_source(&options.file) {
+        Ok(source) => source,
+        Err(err) => io_error("read", &options.file, err),
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if options.subcommand == Subcommand::Lex {
+        if options.trivia {
+            lex_lossless(&options, &source, out);
+        } else {
+            lex_tokens(&options, &source, out);
+        }
+        return;
+    }
 
+    let mut sources = SourceMap::new();
+    let file = sources.add(options.file.clone(), source);
+    if options.subcommand == Subcommand::Parse {
+        let result = driver::parse_file(&mut sources, file);
+        let program = report(&sources, result);
+        let _ = writeln!(out, "{:#?}", program.body);
+        return;
+    }
+
+    let result = driver::compile_file(&mut sources, file);
+    let program = report(&sources, result);
+    match options.subcommand {
+        Subcommand::Lex | Subcommand::Parse => unreachable!(),
+        Subcommand::Check => {},
+        Subcommand::Run => {
+            // the interpreter thread writes to stdout itself
+            drop(out);
+            match interp::run(&program, io::stdout()) {
+                Ok(code) => process::exit(code as i32),
+                Err(err) => {
+                    let location = err.location;
+                    eprintln!(
+                        "runtime error at {}:{}:{}: {}",
+                        sources.path(location.file).display(), location.line, location.column, err.message,
+                    );
+                    process::exit(EXIT_RUNTIME_ERROR);
+                },
+            }
+        },
+        Subcommand::Build => build(&options, &program, &sources),
+    }
+}
@@ -0,0 +1,80 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+
+/// A shared, immutable string. Symbols made by the same [`Interner`] share
+/// one allocation per distinct text, so cloning one is a reference count
+/// bump and comparing equal ones usually stops at the pointer. The text is
+/// freed with the last symbol holding it, not kept for the whole process.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    /// Makes a symbol of its own for `string`, for names created outside
+    /// of lexing.
+    pub fn new(string: &str) -> Symbol {
+        Symbol(Arc::from(string))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+/// Hashes the text, so symbols from different interners still agree.
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Shows the text, so tokens and nodes debug-print as if they held strings.
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// Hands out one [`Symbol`] per distinct string. Each lexer owns one, so the
+/// table goes away with the lexer while the symbols it made live on in the
+/// tokens and nodes that hold them.
+#[derive(Default)]
+pub struct Interner {
+    symbols: HashSet<Symbol>,
+}
+
+impl Interner {
+    /// Returns the symbol for `string`, allocating only the first time this
+    /// interner sees the string.
+    pub fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(string) {
+            return symbol.clone();
+        }
+        let symbol = Symbol::new(string);
+        self.symbols.insert(symbol.clone());
+        symbol
+    }
+}